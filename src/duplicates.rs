@@ -0,0 +1,628 @@
+use crate::error::{DuError, Result};
+use crate::file_tree::{EntryNode, FileTree};
+use crate::size::{Size, SizeFormat};
+use crate::print_tree::count_of;
+use clap::ValueEnum;
+use crypto_hash::{Algorithm, Hasher};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Nombre d'octets hachés au début et à la fin d'un fichier lors du tri rapide.
+const PARTIAL_HASH_BLOCK: u64 = 4 * 1024;
+
+/// Taille des morceaux lus lors de la comparaison octet par octet.
+const COMPARE_CHUNK: usize = 64 * 1024;
+
+/// Fonction de hachage utilisée pour calculer les signatures des fichiers.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    #[default]
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// Nom de l'algorithme tel qu'il apparaît dans les rapports.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    fn algorithm(self) -> Algorithm {
+        match self {
+            HashAlgorithm::Md5 => Algorithm::MD5,
+            HashAlgorithm::Sha1 => Algorithm::SHA1,
+            HashAlgorithm::Sha256 => Algorithm::SHA256,
+            HashAlgorithm::Sha512 => Algorithm::SHA512,
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Un groupe de fichiers au contenu identique.
+pub struct DuplicateGroup {
+    /// Algorithme ayant produit `digest`
+    pub algorithm: HashAlgorithm,
+    /// Signature commune à tous les fichiers du groupe
+    pub digest: String,
+    /// Taille d'un exemplaire
+    pub size: Size,
+    /// Chemins des fichiers identiques, triés
+    pub paths: Vec<PathBuf>,
+    /// Vrai si des fichiers de même signature se sont révélés différents :
+    /// le groupe est alors l'un des sous-groupes réellement identiques
+    pub collision: bool,
+}
+
+impl DuplicateGroup {
+    /// Espace disque occupé inutilement : toutes les copies sauf une.
+    pub fn wasted(&self) -> Size {
+        // toutes les copies sauf une
+        self.size * self.paths.len() as u64 - self.size
+    }
+}
+
+// Convertit une empreinte binaire en hexadécimal
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Hache le contenu lu depuis `reader` par morceaux, sans jamais charger le fichier entier
+fn hash_reader<R: Read>(reader: &mut R, algorithm: HashAlgorithm) -> io::Result<String> {
+    let mut hasher = Hasher::new(algorithm.algorithm());
+    io::copy(reader, &mut hasher)?;
+    Ok(to_hex(&hasher.finish()))
+}
+
+// Empreinte complète du fichier
+fn full_digest(path: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+    hash_reader(&mut BufReader::new(File::open(path)?), algorithm)
+}
+
+// Empreinte rapide : les premiers et derniers PARTIAL_HASH_BLOCK octets.
+// Un fichier assez petit pour être couvert entièrement est haché en entier,
+// l'empreinte obtenue est alors directement son empreinte complète.
+fn partial_digest(path: &Path, size: Size, algorithm: HashAlgorithm) -> io::Result<String> {
+    if size <= Size(2 * PARTIAL_HASH_BLOCK) {
+        return full_digest(path, algorithm);
+    }
+    let mut file = File::open(path)?;
+    let mut hasher = Hasher::new(algorithm.algorithm());
+    io::copy(&mut (&mut file).take(PARTIAL_HASH_BLOCK), &mut hasher)?;
+    file.seek(SeekFrom::End(-(PARTIAL_HASH_BLOCK as i64)))?;
+    io::copy(&mut file.take(PARTIAL_HASH_BLOCK), &mut hasher)?;
+    Ok(to_hex(&hasher.finish()))
+}
+
+// Répartit chaque groupe selon la clé calculée par `key` ; les fichiers illisibles
+// sont écartés et ajoutés à `failures`, et seuls les sous-groupes d'au moins deux
+// fichiers sont conservés. Les clés sont calculées en parallèle, la répartition
+// reste déterministe.
+fn refine<K, F>(groups: Vec<Vec<PathBuf>>, key: F, failures: &mut Vec<DuError>) -> Vec<(K, Vec<PathBuf>)>
+where
+    K: std::hash::Hash + Eq + Ord + Send,
+    F: Fn(&Path) -> io::Result<K> + Sync,
+{
+    // toutes les clés de tous les groupes en une seule passe parallèle
+    let keys: Vec<Vec<io::Result<K>>> = groups
+        .par_iter()
+        .map(|group| group.par_iter().map(|path| key(path)).collect())
+        .collect();
+
+    let mut refined = vec![];
+    for (group, keys) in groups.into_iter().zip(keys) {
+        let mut buckets: HashMap<K, Vec<PathBuf>> = HashMap::new();
+        for (path, k) in group.into_iter().zip(keys) {
+            match k {
+                Ok(k) => buckets.entry(k).or_default().push(path),
+                Err(error) => failures.push(DuError::HashFailure(path, error)),
+            }
+        }
+        let mut buckets: Vec<(K, Vec<PathBuf>)> = buckets
+            .into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .collect();
+        buckets.sort_by(|a, b| a.0.cmp(&b.0));
+        refined.extend(buckets);
+    }
+    refined
+}
+
+// Remplit `buf` autant que possible ; renvoie le nombre d'octets lus (0 en fin de fichier)
+fn read_chunk<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// Compare deux fichiers morceau par morceau ; l'erreur désigne le fichier illisible
+fn same_content(a: &Path, b: &Path) -> Result<bool> {
+    let failure = |path: &Path, error| DuError::HashFailure(path.to_path_buf(), error);
+    let mut reader_a = BufReader::new(File::open(a).map_err(|e| failure(a, e))?);
+    let mut reader_b = BufReader::new(File::open(b).map_err(|e| failure(b, e))?);
+    let mut buf_a = vec![0; COMPARE_CHUNK];
+    let mut buf_b = vec![0; COMPARE_CHUNK];
+    loop {
+        let read_a = read_chunk(&mut reader_a, &mut buf_a).map_err(|e| failure(a, e))?;
+        let read_b = read_chunk(&mut reader_b, &mut buf_b).map_err(|e| failure(b, e))?;
+        if read_a != read_b || buf_a[..read_a] != buf_b[..read_b] {
+            return Ok(false);
+        }
+        if read_a == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+// Sépare un groupe de fichiers de même signature en sous-groupes au contenu
+// réellement identique, en comparant chaque fichier au représentant de chaque
+// sous-groupe. Un fichier dont la comparaison échoue n'apparaît dans aucun
+// sous-groupe et n'est signalé qu'une fois dans `failures` ; plusieurs
+// sous-groupes signifient donc que des fichiers lus en entier diffèrent.
+fn split_by_content(paths: Vec<PathBuf>, failures: &mut Vec<DuError>) -> Vec<Vec<PathBuf>> {
+    let mut subgroups: Vec<Vec<PathBuf>> = vec![];
+    'paths: for path in paths {
+        let mut index = 0;
+        while index < subgroups.len() {
+            match same_content(&subgroups[index][0], &path) {
+                Ok(true) => {
+                    subgroups[index].push(path);
+                    continue 'paths;
+                }
+                Ok(false) => index += 1,
+                Err(error) => {
+                    let path_failed = error.path() == path;
+                    if !failures.iter().any(|failure| failure.path() == error.path()) {
+                        failures.push(error);
+                    }
+                    if path_failed {
+                        continue 'paths;
+                    }
+                    // le représentant est devenu illisible : le suivant le remplace
+                    subgroups[index].remove(0);
+                    if subgroups[index].is_empty() {
+                        subgroups.remove(index);
+                    }
+                }
+            }
+        }
+        subgroups.push(vec![path]);
+    }
+    subgroups
+}
+
+// Vérifie octet par octet un groupe de même signature : renvoie ses
+// sous-groupes d'au moins deux fichiers identiques, et s'il y a collision
+fn verify_group(paths: Vec<PathBuf>, failures: &mut Vec<DuError>) -> (Vec<Vec<PathBuf>>, bool) {
+    let subgroups = split_by_content(paths, failures);
+    let collision = subgroups.len() > 1;
+    // un fichier seul dans son sous-groupe n'a pas de doublon
+    (subgroups.into_iter().filter(|paths| paths.len() >= 2).collect(), collision)
+}
+
+impl FileTree {
+    // Détecte les fichiers identiques sous `path` en trois étapes : regroupement par
+    // taille exacte, empreinte partielle des fichiers de même taille, puis empreinte
+    // complète pour ceux qui sont encore en collision. Les fichiers vides, tous
+    // identiques sans rien occuper, sont écartés. Les fichiers qui n'ont pas
+    // pu être hachés sont ajoutés à `failures`. Les groupes sont indexés par
+    // taille et signature : deux groupes de tailles différentes restent séparés
+    // même si leurs signatures coïncident
+    pub fn doublons(
+        &self,
+        path: &Path,
+        algorithm: HashAlgorithm,
+        failures: &mut Vec<DuError>,
+    ) -> HashMap<(Size, String), Vec<PathBuf>> {
+        // un lien physique déjà compté ailleurs partage le contenu de l'original
+        // sans occuper de place : il n'est pas un doublon
+        let files = self
+            .files_recursive(path)
+            .into_iter()
+            .filter(|file| self.get_apparent_size(file) != Some(Size(0)))
+            .filter(|file| !matches!(self.map.get(file), Some(EntryNode::File { counted_elsewhere: true, .. })))
+            .collect();
+
+        // 1. même taille, sans lire les fichiers
+        let same_size = refine(
+            vec![files],
+            |file| self.get_apparent_size(file).ok_or(io::ErrorKind::NotFound.into()),
+            failures,
+        );
+
+        // 2. même début et même fin
+        let same_size = same_size.into_iter().map(|(_, group)| group).collect();
+        let same_partial = refine(
+            same_size,
+            |file| partial_digest(file, self.get_apparent_size(file).unwrap_or_default(), algorithm),
+            failures,
+        );
+
+        let mut duplicates = HashMap::new();
+        let mut candidates = vec![];
+        let size_of = |group: &[PathBuf]| self.get_apparent_size(&group[0]).unwrap_or_default();
+        for (digest, group) in same_partial {
+            let size = size_of(&group);
+            if size <= Size(2 * PARTIAL_HASH_BLOCK) {
+                // le fichier a été haché en entier
+                duplicates.insert((size, digest), group);
+            } else {
+                candidates.push(group);
+            }
+        }
+
+        // 3. même contenu complet
+        let same_content = refine(candidates, |file| full_digest(file, algorithm), failures);
+        duplicates.extend(same_content.into_iter().map(|(digest, group)| ((size_of(&group), digest), group)));
+        duplicates
+    }
+
+    // Regroupe les doublons trouvés sous `path`, du groupe qui gaspille le plus
+    // d'espace à celui qui en gaspille le moins. Avec `check_for_collisions`,
+    // chaque groupe est vérifié octet par octet ; un groupe dont des fichiers
+    // diffèrent est découpé en sous-groupes identiques, tous signalés comme
+    // collision, et les fichiers illisibles n'apparaissent que dans `failures`.
+    pub fn duplicate_groups(
+        &self,
+        path: &Path,
+        algorithm: HashAlgorithm,
+        check_for_collisions: bool,
+        failures: &mut Vec<DuError>,
+    ) -> Vec<DuplicateGroup> {
+        let mut groups: Vec<DuplicateGroup> = vec![];
+        for ((size, digest), mut paths) in self.doublons(path, algorithm, failures) {
+            paths.sort();
+
+            let (subgroups, collision) = if check_for_collisions {
+                verify_group(paths, failures)
+            } else {
+                (vec![paths], false)
+            };
+            for paths in subgroups {
+                groups.push(DuplicateGroup {
+                    algorithm,
+                    digest: digest.clone(),
+                    size,
+                    paths,
+                    collision,
+                });
+            }
+        }
+
+        // à gaspillage égal, l'ordre des signatures rend la sortie déterministe
+        groups.sort_by(|a, b| {
+            b.wasted()
+                .cmp(&a.wasted())
+                .then_with(|| a.digest.cmp(&b.digest))
+                .then_with(|| a.paths.cmp(&b.paths))
+        });
+        groups
+    }
+
+    // Affiche chaque groupe de doublons avec sa signature, le nombre de fichiers
+    // et l'espace gaspillé ; renvoie les fichiers qui n'ont pas pu être hachés
+    pub fn show_duplicates(
+        &self,
+        path: &Path,
+        algorithm: HashAlgorithm,
+        check_for_collisions: bool,
+        size_format: SizeFormat,
+    ) -> Vec<DuError> {
+        let mut failures = vec![];
+        let groups = self.duplicate_groups(path, algorithm, check_for_collisions, &mut failures);
+        if groups.is_empty() {
+            println!("No duplicate files found in {}", path.to_string_lossy());
+        }
+
+        for group in &groups {
+            println!(
+                "{}:{} ({} files of {}, {} wasted){}",
+                group.algorithm,
+                group.digest,
+                group.paths.len(),
+                size_format.format(group.size),
+                size_format.format(group.wasted()),
+                if group.collision { " [hash collision]" } else { "" }
+            );
+            for file in &group.paths {
+                println!("   {}", file.to_string_lossy());
+            }
+        }
+        if !groups.is_empty() {
+            let wasted: Size = groups.iter().map(DuplicateGroup::wasted).sum();
+            let total = self.get_apparent_size(path).unwrap_or_default();
+            println!(
+                "{}, {} wasted ({:.1}% of {})",
+                count_of(groups.len(), "duplicate group", "duplicate groups"),
+                size_format.format(wasted),
+                wasted.percent_of(total),
+                size_format.format(total)
+            );
+        }
+        failures.sort_by(|a, b| a.path().cmp(b.path()));
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_hash::hex_digest;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_doublons_with_content_comparison() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let file1_path = root.join("file1.txt");
+        let file2_path = root.join("file2.txt");
+        let file3_path = root.join("file3.txt");
+        fs::write(&file1_path, b"content").unwrap();
+        fs::write(&file2_path, b"content").unwrap();
+        fs::write(&file3_path, b"different_content").unwrap();
+
+        let file_tree = FileTree::new(root).expect("Failed to create FileTree");
+        let duplicates = file_tree.doublons(root, HashAlgorithm::Md5, &mut vec![]);
+
+        assert_eq!(duplicates.len(), 1);
+        let duplicate_files = duplicates.get(&(Size(7), hex_digest(Algorithm::MD5, b"content"))).unwrap();
+        assert_eq!(duplicate_files.len(), 2);
+        assert!(duplicate_files.contains(&file1_path));
+        assert!(duplicate_files.contains(&file2_path));
+        assert!(!duplicate_files.contains(&file3_path));
+    }
+
+    #[test]
+    fn test_doublons_without_content_comparison() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("file1.txt"), b"content").unwrap();
+        fs::write(root.join("file2.txt"), b"different_content").unwrap();
+
+        let file_tree = FileTree::new(root).expect("Failed to create FileTree");
+
+        assert!(file_tree.doublons(root, HashAlgorithm::Md5, &mut vec![]).is_empty());
+    }
+
+    /// Tests that empty files are not reported as duplicates of each other.
+    #[test]
+    fn test_duplicate_groups_do_not_depend_on_threads() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        for copy in 0..4 {
+            for content in 0..10 {
+                fs::write(root.join(format!("{content}_{copy}")), vec![content as u8; 20_000 + content]).unwrap();
+            }
+        }
+
+        let groups = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| {
+                let file_tree = FileTree::new(root).unwrap();
+                let groups = file_tree.duplicate_groups(root, HashAlgorithm::Md5, true, &mut vec![]);
+                groups.into_iter().map(|group| (group.digest, group.paths)).collect::<Vec<_>>()
+            })
+        };
+        let sequential = groups(1);
+        assert_eq!(sequential.len(), 10);
+        assert_eq!(groups(4), sequential);
+    }
+
+    #[test]
+    fn test_doublons_ignore_empty_files() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("empty1"), b"").unwrap();
+        fs::write(root.join("empty2"), b"").unwrap();
+        fs::write(root.join("file1.txt"), b"content").unwrap();
+        fs::write(root.join("file2.txt"), b"content").unwrap();
+
+        let file_tree = FileTree::new(root).expect("Failed to create FileTree");
+        let duplicates = file_tree.doublons(root, HashAlgorithm::Md5, &mut vec![]);
+
+        let groups: Vec<&Vec<PathBuf>> = duplicates.values().collect();
+        assert_eq!(groups, vec![&vec![root.join("file1.txt"), root.join("file2.txt")]]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_doublons_ignore_hard_links() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("a"), b"content").unwrap();
+        fs::hard_link(root.join("a"), root.join("b")).unwrap();
+
+        let file_tree = FileTree::new(root).unwrap();
+        let duplicates = file_tree.doublons(root, HashAlgorithm::Md5, &mut vec![]);
+        assert!(duplicates.is_empty());
+
+        // a real copy is still reported, once, next to the first link
+        fs::write(root.join("c"), b"content").unwrap();
+        let file_tree = FileTree::new(root).unwrap();
+        let duplicates = file_tree.doublons(root, HashAlgorithm::Md5, &mut vec![]);
+        let group = duplicates.get(&(Size(7), hex_digest(Algorithm::MD5, b"content"))).unwrap();
+        assert_eq!(group.len(), 2);
+        assert!(group.contains(&root.join("c")));
+    }
+
+    #[test]
+    fn test_doublons_large_files_differing_in_the_middle() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let content = vec![b'x'; 3 * PARTIAL_HASH_BLOCK as usize];
+        let mut altered = content.clone();
+        altered[content.len() / 2] = b'y';
+        fs::write(root.join("a"), &content).unwrap();
+        fs::write(root.join("b"), &content).unwrap();
+        fs::write(root.join("c"), &altered).unwrap();
+
+        let file_tree = FileTree::new(root).unwrap();
+        let duplicates = file_tree.doublons(root, HashAlgorithm::Md5, &mut vec![]);
+
+        // same size, same first and last blocks, but only a and b are identical
+        assert_eq!(duplicates.len(), 1);
+        let group = duplicates.get(&(Size(content.len() as u64), hex_digest(Algorithm::MD5, &content))).unwrap();
+        assert_eq!(group.len(), 2);
+        assert!(!group.contains(&root.join("c")));
+    }
+
+    #[test]
+    fn test_groups_sorted_by_wasted_space() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        // 3 copies of 2 bytes: 4 bytes wasted
+        for name in ["a1", "a2", "a3"] {
+            fs::write(root.join(name), b"aa").unwrap();
+        }
+        // 2 copies of 10 bytes: 10 bytes wasted
+        for name in ["b1", "b2"] {
+            fs::write(root.join(name), b"bbbbbbbbbb").unwrap();
+        }
+        fs::write(root.join("unique"), b"unique").unwrap();
+
+        let file_tree = FileTree::new(root).unwrap();
+        let groups = file_tree.duplicate_groups(root, HashAlgorithm::Md5, true, &mut vec![]);
+
+        assert_eq!(groups.len(), 2);
+        assert!(groups.iter().all(|group| !group.collision));
+        assert_eq!(groups[0].paths, vec![root.join("b1"), root.join("b2")]);
+        assert_eq!(groups[0].wasted(), Size(10));
+        assert_eq!(groups[1].paths.len(), 3);
+        assert_eq!(groups[1].wasted(), Size(4));
+    }
+
+    #[test]
+    fn test_doublons_with_sha256() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let content = vec![b'q'; 3 * PARTIAL_HASH_BLOCK as usize];
+        fs::write(root.join("small1"), b"content").unwrap();
+        fs::write(root.join("small2"), b"content").unwrap();
+        fs::write(root.join("large1"), &content).unwrap();
+        fs::write(root.join("large2"), &content).unwrap();
+
+        let file_tree = FileTree::new(root).unwrap();
+        let duplicates = file_tree.doublons(root, HashAlgorithm::Sha256, &mut vec![]);
+
+        // both the small-file and full-hash stages key groups by the chosen digest
+        assert_eq!(duplicates.len(), 2);
+        assert!(duplicates.contains_key(&(Size(7), hex_digest(Algorithm::SHA256, b"content"))));
+        assert!(duplicates.contains_key(&(Size(content.len() as u64), hex_digest(Algorithm::SHA256, &content))));
+
+        let groups = file_tree.duplicate_groups(root, HashAlgorithm::Sha256, false, &mut vec![]);
+        assert!(groups.iter().all(|group| group.algorithm.name() == "sha256"));
+    }
+
+    #[test]
+    fn test_split_by_content() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let big = vec![b'z'; COMPARE_CHUNK + 10];
+        let mut big_altered = big.clone();
+        big_altered[COMPARE_CHUNK + 5] = b'w';
+        fs::write(root.join("a"), &big).unwrap();
+        fs::write(root.join("b"), &big_altered).unwrap();
+        fs::write(root.join("c"), &big).unwrap();
+        fs::write(root.join("d"), &big_altered).unwrap();
+
+        // pretend the four files share a digest, as a crafted collision would
+        let mut failures = vec![];
+        let subgroups = split_by_content(
+            vec![root.join("a"), root.join("b"), root.join("c"), root.join("d")],
+            &mut failures,
+        );
+
+        assert_eq!(
+            subgroups,
+            vec![
+                vec![root.join("a"), root.join("c")],
+                vec![root.join("b"), root.join("d")],
+            ]
+        );
+        assert!(failures.is_empty());
+    }
+
+    /// Tests that a file which cannot be compared is left out of every
+    /// subgroup, whether it comes first or among the others.
+    #[test]
+    fn test_split_by_content_skips_unreadable_files() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("a"), b"content").unwrap();
+        fs::write(root.join("b"), b"content").unwrap();
+        let missing = root.join("missing");
+
+        for paths in [
+            vec![root.join("a"), missing.clone(), root.join("b")],
+            vec![missing.clone(), root.join("a"), root.join("b")],
+        ] {
+            let mut failures = vec![];
+            let subgroups = split_by_content(paths, &mut failures);
+            assert_eq!(subgroups, vec![vec![root.join("a"), root.join("b")]]);
+            assert_eq!(failures.len(), 1);
+            assert_eq!(failures[0].path(), missing);
+        }
+    }
+
+    /// Tests that an unreadable file neither forms its own group nor marks
+    /// its real duplicates as a collision, while a file that really differs does.
+    #[test]
+    fn test_verify_group() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("a"), b"content").unwrap();
+        fs::write(root.join("b"), b"content").unwrap();
+        fs::write(root.join("other"), b"CONTENT").unwrap();
+
+        let mut failures = vec![];
+        let group = vec![root.join("a"), root.join("missing"), root.join("b")];
+        assert_eq!(verify_group(group, &mut failures), (vec![vec![root.join("a"), root.join("b")]], false));
+        assert_eq!(failures.len(), 1);
+
+        let mut failures = vec![];
+        let group = vec![root.join("a"), root.join("other"), root.join("b")];
+        assert_eq!(verify_group(group, &mut failures), (vec![vec![root.join("a"), root.join("b")]], true));
+        assert!(failures.is_empty());
+    }
+
+    #[test]
+    fn test_hash_failure_names_the_file() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("kept"), b"content").unwrap();
+        fs::write(root.join("removed"), b"content").unwrap();
+
+        let file_tree = FileTree::new(root).unwrap();
+        // the file disappears between the scan and the hashing
+        fs::remove_file(root.join("removed")).unwrap();
+        let mut failures = vec![];
+        let duplicates = file_tree.doublons(root, HashAlgorithm::Md5, &mut failures);
+
+        assert!(duplicates.is_empty());
+        assert_eq!(failures.len(), 1);
+        assert!(matches!(&failures[0], DuError::HashFailure(path, _) if *path == root.join("removed")));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::size::SizeError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors reported by the disk usage analyser, each one naming the path that caused it.
+#[derive(Debug)]
+pub enum DuError {
+    /// The path given as the root of the tree is not a directory.
+    NotADirectory(PathBuf),
+    /// The path could not be read because of its permissions.
+    PermissionDenied(PathBuf),
+    /// Any other I/O failure while reading the path.
+    Io(PathBuf, io::Error),
+    /// The file could not be read while computing or verifying its digest.
+    HashFailure(PathBuf, io::Error),
+    /// An --include or --exclude pattern could not be compiled.
+    InvalidPattern(String, String),
+    /// A saved tree could not be loaded from this file.
+    InvalidInput(PathBuf, String),
+    /// A size given on the command line, such as --min-size, could not be parsed.
+    InvalidSize(String, SizeError),
+    /// The output could not be written to this file, `-` being the standard output.
+    Write(PathBuf, io::Error),
+}
+
+/// Result type used throughout the crate.
+pub type Result<T> = std::result::Result<T, DuError>;
+
+impl DuError {
+    /// Wraps an I/O error raised while reading `path`.
+    pub fn io(path: &Path, error: io::Error) -> Self {
+        if error.kind() == io::ErrorKind::PermissionDenied {
+            DuError::PermissionDenied(path.to_path_buf())
+        } else {
+            DuError::Io(path.to_path_buf(), error)
+        }
+    }
+
+    /// Returns the path that caused the error; for a bad pattern or size, the text itself.
+    pub fn path(&self) -> &Path {
+        match self {
+            DuError::NotADirectory(path)
+            | DuError::PermissionDenied(path)
+            | DuError::Io(path, _)
+            | DuError::HashFailure(path, _)
+            | DuError::InvalidInput(path, _)
+            | DuError::Write(path, _) => path,
+            DuError::InvalidPattern(text, _) | DuError::InvalidSize(text, _) => Path::new(text),
+        }
+    }
+
+    /// Short description used as a marker in the tree, e.g. `permission denied`.
+    pub fn reason(&self) -> String {
+        match self {
+            DuError::NotADirectory(_) => "not a directory".to_string(),
+            DuError::PermissionDenied(_) => "permission denied".to_string(),
+            DuError::Io(_, error) | DuError::HashFailure(_, error) | DuError::Write(_, error) => error.kind().to_string(),
+            DuError::InvalidPattern(..) => "invalid pattern".to_string(),
+            DuError::InvalidInput(..) => "invalid input".to_string(),
+            DuError::InvalidSize(..) => "invalid size".to_string(),
+        }
+    }
+
+    /// Process exit code for this kind of error; each variant has its own, above the
+    /// code 2 that clap uses for command-line usage errors.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            DuError::NotADirectory(_) => 3,
+            DuError::PermissionDenied(_) => 4,
+            DuError::Io(..) => 5,
+            DuError::HashFailure(..) => 6,
+            DuError::InvalidPattern(..) => 7,
+            DuError::InvalidInput(..) => 8,
+            DuError::InvalidSize(..) => 9,
+            DuError::Write(..) => 10,
+        }
+    }
+}
+
+/// Implements Display for user-friendly formatting of DuError.
+impl fmt::Display for DuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path().to_string_lossy();
+        match self {
+            DuError::NotADirectory(_) => write!(f, "'{}' is not a directory", path),
+            DuError::PermissionDenied(_) => write!(f, "cannot read '{}': permission denied", path),
+            DuError::Io(_, error) => write!(f, "cannot read '{}': {}", path, error),
+            DuError::HashFailure(_, error) => write!(f, "cannot hash '{}': {}", path, error),
+            DuError::InvalidPattern(_, message) => write!(f, "invalid pattern '{}': {}", path, message),
+            DuError::InvalidInput(_, message) => write!(f, "cannot load '{}': {}", path, message),
+            DuError::InvalidSize(_, error) => write!(f, "invalid size '{}': {}", path, error),
+            DuError::Write(_, error) => write!(f, "cannot write '{}': {}", path, error),
+        }
+    }
+}
+
+/// Implements Error so that the underlying I/O error stays reachable.
+impl Error for DuError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DuError::Io(_, error) | DuError::HashFailure(_, error) | DuError::Write(_, error) => Some(error),
+            DuError::InvalidSize(_, error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Module containing tests for the DuError type.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Tests that permission errors get their own variant.
+    #[test]
+    fn test_io_permission_denied() {
+        let error = DuError::io(Path::new("secret"), io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(error, DuError::PermissionDenied(_)));
+        assert_eq!(error.path(), Path::new("secret"));
+        assert_eq!(error.to_string(), "cannot read 'secret': permission denied");
+    }
+
+    /// Tests that every variant maps to a distinct exit code, other than success and
+    /// clap's usage error code.
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let path = Path::new("p");
+        let errors = [
+            DuError::NotADirectory(path.to_path_buf()),
+            DuError::PermissionDenied(path.to_path_buf()),
+            DuError::Io(path.to_path_buf(), io::Error::from(io::ErrorKind::NotFound)),
+            DuError::HashFailure(path.to_path_buf(), io::Error::from(io::ErrorKind::NotFound)),
+            DuError::InvalidPattern("[".to_string(), "unclosed character class".to_string()),
+            DuError::InvalidInput(path.to_path_buf(), "unsupported version".to_string()),
+            DuError::InvalidSize("10Q".to_string(), SizeError::UnknownUnit("Q".to_string())),
+            DuError::Write(path.to_path_buf(), io::Error::from(io::ErrorKind::StorageFull)),
+        ];
+        let codes: HashSet<u8> = errors.iter().map(DuError::exit_code).collect();
+        assert_eq!(codes.len(), errors.len());
+        assert!(codes.iter().all(|&code| code > 2));
+    }
+}
@@ -1,224 +1,1002 @@
 use crate::size::Size;
-use crate::size;
+use clap::ValueEnum;
+use rayon::prelude::*;
 use std::fs;
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::io::{Error, ErrorKind, Result};
-use crypto_hash::{hex_digest, Algorithm};
+use crate::error::{DuError, Result};
+use crate::filter::{IgnoreMode, IgnoreStack, PathFilter};
+use std::io;
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 pub struct FileTree {
     root: PathBuf,
     pub map: HashMap<PathBuf, EntryNode>,
+    size_mode: SizeMode,
+    errors: Vec<DuError>,
 }
 
-#[allow(dead_code)]
+// Taille renvoyée par get_size et utilisée pour le tri et l'affichage
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SizeMode {
+    // taille apparente, celle que renvoie metadata.len()
+    #[default]
+    Apparent,
+    // espace réellement alloué sur le disque (st_blocks * 512), comme GNU du
+    Disk,
+}
+
+// Un nœud de l'arbre : un fichier avec ses tailles, un répertoire avec
+// ses tailles totales et la liste de ses enfants directs, ou un lien
+// symbolique non suivi avec sa cible.
+// `apparent_size` est la somme des longueurs des fichiers ; `disk_size` est
+// l'espace alloué, y compris les blocs des répertoires eux-mêmes.
+// `counted_elsewhere` indique un fichier déjà compté ailleurs dans l'arbre
+// (autre lien physique, ou même fichier atteint par un lien symbolique) :
+// sa taille n'entre pas dans celle de ses répertoires parents.
+// `is_loop` indique un lien qui ramène vers l'un de ses répertoires parents.
+// Un point de montage non parcouru (option one_file_system) garde le numéro
+// du périphérique monté et, si on la connaît, une description du système de
+// fichiers ; il ne compte pour rien dans la taille de ses parents.
+// Un chemin qui n'a pas pu être lu pendant le parcours (droits insuffisants,
+// fichier supprimé entre-temps...) garde la description de l'erreur.
+// `modified` est la date de dernière modification, si le système la fournit.
+#[derive(Clone)]
 pub enum EntryNode {
-    File(Size),
-    Directory(Size),
+    File { apparent_size: Size, disk_size: Size, counted_elsewhere: bool, modified: Option<SystemTime> },
+    Directory { apparent_size: Size, disk_size: Size, children: Vec<PathBuf>, modified: Option<SystemTime> },
+    Symlink { target: PathBuf, apparent_size: Size, disk_size: Size, is_loop: bool, modified: Option<SystemTime> },
+    MountPoint { device: u64, filesystem: Option<String> },
+    Error { message: String },
 }
 
-pub fn calculate_size(chemin: &Path) -> Result<u64> {
-    if !chemin.is_file() {
-        return Err(Error::new(ErrorKind::InvalidInput, "Not a file"));
+impl EntryNode {
+    // Renvoie la taille apparente du nœud (taille cumulée pour un répertoire)
+    pub fn apparent_size(&self) -> Size {
+        match self {
+            EntryNode::File { apparent_size, .. } => *apparent_size,
+            EntryNode::Directory { apparent_size, .. } => *apparent_size,
+            EntryNode::Symlink { apparent_size, .. } => *apparent_size,
+            EntryNode::MountPoint { .. } | EntryNode::Error { .. } => Size(0),
+        }
     }
-    let chemin = chemin.to_path_buf();
-    let file_size: u64 = fs::metadata(&chemin)?.len();
-    Ok(file_size)
-}
 
-pub fn calculate_directory_size(directory: &Path) -> Result<u64> {
-    if !directory.is_dir() {
-        return Err(Error::new(ErrorKind::InvalidInput, "Not a directory"));
+    // Renvoie l'espace alloué sur le disque (cumulé pour un répertoire)
+    pub fn disk_size(&self) -> Size {
+        match self {
+            EntryNode::File { disk_size, .. } => *disk_size,
+            EntryNode::Directory { disk_size, .. } => *disk_size,
+            EntryNode::Symlink { disk_size, .. } => *disk_size,
+            EntryNode::MountPoint { .. } | EntryNode::Error { .. } => Size(0),
+        }
     }
 
-    let mut total_size: u64 = 0;
+    // Renvoie la taille correspondant au mode demandé
+    pub fn size(&self, mode: SizeMode) -> Size {
+        match mode {
+            SizeMode::Apparent => self.apparent_size(),
+            SizeMode::Disk => self.disk_size(),
+        }
+    }
 
-    for entry in fs::read_dir(directory)? {
-        let entry = entry?;
-        let path: PathBuf = entry.path();
+    // Date de dernière modification du nœud
+    pub fn modified(&self) -> Option<SystemTime> {
+        match self {
+            EntryNode::File { modified, .. }
+            | EntryNode::Directory { modified, .. }
+            | EntryNode::Symlink { modified, .. } => *modified,
+            EntryNode::MountPoint { .. } | EntryNode::Error { .. } => None,
+        }
+    }
 
-        if path.is_dir() {
-            // Recursive call for subdirectories
-            total_size += calculate_directory_size(&path)?;
-        } else {
-            // Add the size of the file
-            total_size += fs::metadata(&path)?.len();
+    // Un fichier creux occupe sur le disque moins de la moitié de sa taille
+    // apparente ; en dessous d'un bloc, un fichier peut être rangé dans son
+    // inœud sans aucun bloc alloué, ce qui n'en fait pas un fichier creux
+    pub fn is_sparse(&self) -> bool {
+        match self {
+            EntryNode::File { apparent_size, disk_size, .. } => {
+                *apparent_size >= SPARSE_MIN_SIZE && *disk_size < *apparent_size / 2
+            }
+            _ => false,
         }
     }
+}
 
-    Ok(total_size)
+// Taille apparente en dessous de laquelle un fichier n'est jamais signalé
+// comme creux : un bloc de la plupart des systèmes de fichiers
+const SPARSE_MIN_SIZE: Size = Size(4096);
+
+// Espace alloué pour une entrée, en blocs de 512 octets
+#[cfg(unix)]
+fn allocated_size(metadata: &fs::Metadata) -> Size {
+    use std::os::unix::fs::MetadataExt;
+    Size(metadata.blocks() * 512)
 }
 
+// Sans st_blocks, on se rabat sur la taille apparente
+#[cfg(not(unix))]
+fn allocated_size(metadata: &fs::Metadata) -> Size {
+    Size(metadata.len())
+}
 
-impl FileTree {
-    // creation d'un nouvel arbre de fichier
-    pub fn new(root: &Path) -> Result<Self> {
-            let root = root.to_path_buf() ; // mettre root en PathBuf pour faciliter la manipulation
-            // Verifier si root est un repertoire , sinon afficher une erreur
-            if root.is_dir() {
-                let size = size::Size(calculate_directory_size(&root).unwrap());
-
-                let mut map = HashMap::new(); // stocker les nœuds de l'arbre 
-                //let root_path = root.as_path(); // Obtenir une référence &Path du chemin racine.
-                let root_size = Size::new(size.0).map_err(|e| {
-                    Error::new(ErrorKind::InvalidData, format!("Invalid size: {}", e))
-                })?;
-                let root_node = EntryNode::Directory(root_size); // Créeer le noeud racine
-                map.insert(root.clone(), root_node);
-        
-                Ok(FileTree { root, map }) // Retourne une nouvelle instance avec le chemin racine et la HashMap
-            } else {
-                Err(Error::new(ErrorKind::InvalidInput, "Le chemin racine n'est pas un répertoire"))
+// Nœud d'un lien symbolique qui n'est pas suivi, de la taille du lien lui-même
+fn symlink_node(path: &Path, is_loop: bool) -> io::Result<EntryNode> {
+    let link_metadata = fs::symlink_metadata(path)?;
+    Ok(EntryNode::Symlink {
+        target: fs::read_link(path)?,
+        apparent_size: Size(link_metadata.len()),
+        disk_size: allocated_size(&link_metadata),
+        is_loop,
+        modified: link_metadata.modified().ok(),
+    })
+}
+
+// Traitement des liens symboliques pendant le parcours
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    // ne suit aucun lien, même la racine (comme du -P)
+    Never,
+    // ne suit que la racine donnée en ligne de commande (comme du -H)
+    #[default]
+    CommandLine,
+    // suit tous les liens, en évitant les boucles (comme du -L)
+    Always,
+}
+
+// Options du parcours du disque
+#[derive(Clone, Debug, Default)]
+pub struct ScanOptions {
+    // compte un fichier autant de fois qu'il a de liens physiques
+    pub count_links: bool,
+    // liens symboliques à suivre
+    pub symlinks: SymlinkPolicy,
+    // ne traverse pas les points de montage (comme du -x)
+    pub one_file_system: bool,
+    // motifs --include / --exclude, appliqués pendant le parcours
+    pub filter: PathFilter,
+    // prise en compte des fichiers .gitignore et .duignore
+    pub ignore_mode: IgnoreMode,
+}
+
+// Critères qu'un fichier doit remplir pour être affiché ; un répertoire est
+// affiché s'il contient un tel fichier, même indirectement
+#[derive(Clone, Debug, Default)]
+pub struct EntryFilter<'a> {
+    // extension, avec ou sans point
+    pub file_extension: Option<&'a str>,
+    // taille maximale d'un fichier
+    pub max_size: Option<Size>,
+    // fichiers modifiés après cette date
+    pub newer_than: Option<SystemTime>,
+    // fichiers modifiés avant cette date
+    pub older_than: Option<SystemTime>,
+}
+
+impl EntryFilter<'_> {
+    // Vrai si au moins un critère est donné
+    pub fn is_active(&self) -> bool {
+        self.file_extension.is_some()
+            || self.max_size.is_some()
+            || self.newer_than.is_some()
+            || self.older_than.is_some()
+    }
+
+    // Vrai si le fichier `path` remplit tous les critères ; sans date connue,
+    // un fichier ne passe aucun critère d'âge
+    fn accepts(&self, path: &Path, size: Size, modified: Option<SystemTime>) -> bool {
+        let extension_matches = self.file_extension.is_none_or(|file_extension| {
+            let wanted = file_extension.strip_prefix('.').unwrap_or(file_extension);
+            path.extension().is_some_and(|ext| ext == wanted)
+        });
+        extension_matches
+            && self.max_size.is_none_or(|max_size| size <= max_size)
+            && self.newer_than.is_none_or(|date| modified.is_some_and(|modified| modified >= date))
+            && self.older_than.is_none_or(|date| modified.is_some_and(|modified| modified <= date))
+    }
+}
+
+// Identifiant d'un fichier sur le disque : (périphérique, inœud)
+pub type InodeId = (u64, u64);
+
+// Taille du contenu qui passe un filtre, pour chaque entrée qui en contient
+pub type MatchingSizes = HashMap<PathBuf, Size>;
+
+#[cfg(unix)]
+fn inode_id(metadata: &fs::Metadata) -> Option<InodeId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode_id(_metadata: &fs::Metadata) -> Option<InodeId> {
+    None
+}
+
+#[cfg(unix)]
+fn device_id(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_id(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+// Décode un champ de /proc/self/mounts, où les espaces sont écrits \040
+#[cfg(target_os = "linux")]
+fn unescape_mount_field(field: &str) -> String {
+    field
+        .replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+// Décrit le système de fichiers monté sur `path` : "type source"
+#[cfg(target_os = "linux")]
+fn mount_description(path: &Path) -> Option<String> {
+    let mount_point = fs::canonicalize(path).ok()?;
+    let mounts = fs::read_to_string("/proc/self/mounts").ok()?;
+    // le dernier montage sur un chemin est celui qui est visible
+    mounts.lines().rev().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            [source, target, fstype, ..] if Path::new(&unescape_mount_field(target)) == mount_point => {
+                Some(format!("{} {}", fstype, unescape_mount_field(source)))
             }
+            _ => None,
+        }
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mount_description(_path: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+fn has_several_links(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn has_several_links(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+// Ce que le parcours doit faire d'une entrée d'un répertoire
+enum Inspected {
+    // un répertoire à parcourir ; atteint par un lien suivi, il porte l'inœud
+    // du répertoire et le nœud du lien, qui le remplace si ce répertoire est
+    // déjà parcouru par un autre lien
+    SubDirectory(Option<(InodeId, EntryNode)>),
+    // un nœud terminal, avec son inœud s'il peut être atteint par plusieurs chemins
+    Node(EntryNode, Option<InodeId>),
+}
+
+// État partagé entre les threads pendant le parcours
+struct Scanner {
+    options: ScanOptions,
+    // racine du parcours, à laquelle les motifs de filtre sont relatifs
+    root: PathBuf,
+    // périphérique de la racine, pour l'option one_file_system
+    root_device: Option<u64>,
+    map: Mutex<HashMap<PathBuf, EntryNode>>,
+    // chemin canonique de la racine, pour reconnaître les liens vers un
+    // répertoire que le parcours atteint déjà directement
+    canonical_root: PathBuf,
+    // fichiers dont l'inœud peut être atteint par plusieurs chemins, et
+    // répertoires atteints par un lien suivi
+    shared_inodes: Mutex<HashMap<PathBuf, InodeId>>,
+    // nœud de chaque lien suivi vers un répertoire, qui prend sa place si un
+    // autre lien, avant lui dans l'ordre trié, atteint le même répertoire
+    followed_links: Mutex<HashMap<PathBuf, EntryNode>>,
+    // chemins qui n'ont pas pu être lus
+    errors: Mutex<Vec<DuError>>,
+}
+
+impl Scanner {
+    // Nœud à enregistrer à la place du répertoire `path` s'il appartient à un
+    // autre système de fichiers que la racine et qu'on ne doit pas le traverser
+    fn mount_point(&self, path: &Path, metadata: &fs::Metadata) -> Option<EntryNode> {
+        if !self.options.one_file_system {
+            return None;
+        }
+        let device = device_id(metadata)?;
+        (Some(device) != self.root_device).then(|| EntryNode::MountPoint {
+            device,
+            filesystem: mount_description(path),
+        })
     }
 
-    pub fn get_root(&self) -> &PathBuf {
-        &self.root
+    // Enregistre une erreur et renvoie le nœud qui la signale dans l'arbre
+    fn record_error(&self, error: DuError) -> EntryNode {
+        let node = EntryNode::Error { message: error.reason() };
+        self.errors.lock().unwrap().push(error);
+        node
     }
 
-    pub fn get_children(&self, path: &Path, lexicographic_sort: bool, file_extension: Option<&str>) -> Option<Vec<PathBuf>> {
-            if let Some(node) = self.map.get(path) {
-            //let mut vec: Vec<PathBuf> = vec![];
-            match node {
-                EntryNode::File(_) => Some(vec![]),
-                EntryNode::Directory(_) => {
-                    let children: Vec<PathBuf> = fs::read_dir(path)
-                        .ok()?
-                        .filter_map(|entry| {
-                            entry.ok().map(|e| e.path())
-                        })
-                        .filter(|child| child.is_dir())
-                        .collect();
-                        
-                    let mut normalized_children: Vec<PathBuf> = children
-                        .iter()
-                        .map(|child| child.strip_prefix(".").unwrap_or(child))
-                        .map(|child| child.to_path_buf())
-                        .collect();
-
-                    let files = self.files(path.to_path_buf());
-                    for file in files {
-                        normalized_children.push(file)
+    // Examine une entrée de répertoire sans la parcourir
+    fn inspect_entry(&self, entry: &fs::DirEntry, ancestors: &[InodeId]) -> io::Result<Inspected> {
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        let follow_links = self.options.symlinks == SymlinkPolicy::Always;
+
+        if file_type.is_dir() {
+            // le périphérique n'est lu que s'il faut rester sur le système de fichiers
+            if self.options.one_file_system {
+                if let Some(mount_point) = self.mount_point(&path, &entry.metadata()?) {
+                    return Ok(Inspected::Node(mount_point, None));
+                }
+            }
+            return Ok(Inspected::SubDirectory(None));
+        }
+
+        let metadata = if file_type.is_symlink() {
+            let target_metadata = if follow_links { fs::metadata(&path).ok() } else { None };
+            let is_loop = target_metadata.as_ref().is_some_and(|target| {
+                target.is_dir() && inode_id(target).is_none_or(|id| ancestors.contains(&id))
+            });
+            match target_metadata {
+                Some(target) if target.is_dir() && !is_loop => {
+                    if let Some(mount_point) = self.mount_point(&path, &target) {
+                        return Ok(Inspected::Node(mount_point, None));
                     }
-                    
-                    // Apply filtering based on file extension
-                    if let Some(file_extension) = file_extension {
-                        normalized_children.retain(|child| {
-                            child.extension().map_or(false, |ext| ext == file_extension)
-                        });
+                    // un répertoire situé sous la racine est parcouru par son propre
+                    // chemin : le lien n'est pas suivi, pour ne pas le compter deux fois
+                    let link = symlink_node(&path, false)?;
+                    if fs::canonicalize(&path).is_ok_and(|target| target.starts_with(&self.canonical_root)) {
+                        return Ok(Inspected::Node(link, None));
                     }
-
-                    // Apply sorting
-                    if lexicographic_sort {
-                        normalized_children.sort();
-
-                    // sort the children in increasing order
-                    // so la fonction dispay_tree() de print_tree.rs
-                    // can print them in decreasing order
-                    } else {
-                        normalized_children.sort_by(|a, b| {
-                            let size_a = self.get_size(a).unwrap().0;
-                            let size_b = self.get_size(b).unwrap().0;
-                            size_a.cmp(&size_b)
-                        });
-                    } 
-                    //return the children
-                    Some(normalized_children)
+                    return Ok(Inspected::SubDirectory(inode_id(&target).map(|id| (id, link))));
                 }
+                Some(target) if !target.is_dir() => target,
+                // lien non suivi, cassé, ou qui boucle
+                _ => return Ok(Inspected::Node(symlink_node(&path, is_loop)?, None)),
             }
+        } else {
+            entry.metadata()?
+        };
+
+        // en suivant les liens, un même fichier peut être atteint plusieurs fois
+        let shared_inode = if follow_links || has_several_links(&metadata) {
+            inode_id(&metadata)
         } else {
             None
+        };
+        let node = EntryNode::File {
+            apparent_size: Size(metadata.len()),
+            disk_size: allocated_size(&metadata),
+            counted_elsewhere: false,
+            modified: metadata.modified().ok(),
+        };
+        Ok(Inspected::Node(node, shared_inode))
+    }
+
+    // Décide si une entrée d'un répertoire fait partie de l'arbre. Renvoie None
+    // pour une entrée écartée, sinon indique si elle est ignorée par les
+    // fichiers .gitignore et .duignore.
+    fn admit(&self, entry: &fs::DirEntry, ignores: &IgnoreStack) -> Option<bool> {
+        let path = entry.path();
+        // une entrée exclue n'est ni inspectée ni, pour un répertoire, parcourue
+        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+        let relative = path.strip_prefix(&self.root).unwrap_or(&path);
+        if self.options.filter.excludes(relative, is_dir) {
+            return None;
+        }
+        // en mode OnlyIgnored, les répertoires non ignorés sont parcourus
+        // pour y chercher des entrées ignorées
+        let ignored = self.options.ignore_mode != IgnoreMode::Off && ignores.is_ignored(&path, is_dir);
+        match self.options.ignore_mode {
+            IgnoreMode::Respect if ignored => None,
+            // comme git et ignore::WalkBuilder, le dépôt lui-même est écarté
+            IgnoreMode::Respect if is_dir && entry.file_name() == ".git" => None,
+            IgnoreMode::OnlyIgnored if !ignored && !is_dir => None,
+            _ => Some(ignored),
         }
     }
-    
 
-    pub fn get_size(&self, path: &Path) -> Option<Size> {
-        if path.is_file() {
-            let size = calculate_size(path).unwrap() ;
-            Some(size::Size(size))
+    // Règles d'exclusion en vigueur dans le sous-répertoire `sub_directory`
+    fn ignores_below(&self, ignores: &IgnoreStack, sub_directory: &Path, ignored: bool) -> IgnoreStack {
+        match self.options.ignore_mode {
+            IgnoreMode::Off => IgnoreStack::default(),
+            _ => {
+                let mut errors = vec![];
+                let ignores = ignores.descend(sub_directory, ignored, &mut errors);
+                self.errors.lock().unwrap().extend(errors);
+                ignores
+            }
         }
-        else if path.is_dir() {
-            let size = calculate_directory_size(path).unwrap();
-            Some(size::Size(size))
+    }
+
+    // Les blocs du répertoire lui-même comptent dans l'espace disque, sauf
+    // s'ils ne sont pas ignorés alors qu'on ne compte que les octets ignorés
+    fn own_disk_size(&self, metadata: &fs::Metadata, ignores: &IgnoreStack) -> Size {
+        if self.options.ignore_mode == IgnoreMode::OnlyIgnored && !ignores.inside_ignored() {
+            Size(0)
+        } else {
+            allocated_size(metadata)
         }
-        else {
-            None
+    }
+
+    // Un répertoire vide disparaît de l'arbre s'il n'a été parcouru que pour y
+    // chercher des entrées : non ignoré en mode OnlyIgnored, ou ni inclus ni
+    // dans un répertoire inclus avec --include
+    fn prunes_if_empty(&self, directory: &Path, ignored: bool) -> bool {
+        let relative = directory.strip_prefix(&self.root).unwrap_or(directory);
+        (self.options.ignore_mode == IgnoreMode::OnlyIgnored && !ignored)
+            || !self.options.filter.includes_directory(relative)
+    }
+
+    // Parcourt récursivement `directory` une seule fois : chaque fichier et
+    // sous-répertoire est inséré dans la map. Les sous-répertoires sont
+    // parcourus en parallèle par le pool de threads de rayon (vol de tâches).
+    // Les tailles des répertoires sont calculées ensuite par `sum_sizes`.
+    // `ancestors` contient les inœuds des répertoires parents, pour détecter
+    // les liens symboliques qui forment une boucle, `ignores` les règles des
+    // fichiers .gitignore et .duignore en vigueur dans `directory`.
+    // Une entrée illisible devient un nœud Error et le parcours continue.
+    fn scan_directory(&self, directory: &Path, mut ancestors: Vec<InodeId>, ignores: IgnoreStack) {
+        // suit le lien si `directory` en est un : on ne l'atteint que si on devait le suivre
+        let read_dir = fs::metadata(directory).and_then(|metadata| Ok((metadata, fs::read_dir(directory)?)));
+        let (own_metadata, read_dir) = match read_dir {
+            Ok(read_dir) => read_dir,
+            Err(error) => {
+                let node = self.record_error(DuError::io(directory, error));
+                self.map.lock().unwrap().insert(directory.to_path_buf(), node);
+                return;
+            }
+        };
+        ancestors.extend(inode_id(&own_metadata));
+
+        let mut children: Vec<PathBuf> = vec![];
+        let mut nodes: Vec<(PathBuf, EntryNode)> = vec![];
+        let mut shared_inodes: Vec<(PathBuf, InodeId)> = vec![];
+        let mut sub_directories: Vec<(PathBuf, bool)> = vec![];
+
+        for entry in read_dir {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    // pas de chemin pour cette entrée : l'erreur est attribuée au répertoire
+                    self.record_error(DuError::io(directory, error));
+                    continue;
+                }
+            };
+            let Some(ignored) = self.admit(&entry, &ignores) else {
+                continue;
+            };
+            let path: PathBuf = entry.path();
+            children.push(path.clone());
+
+            match self.inspect_entry(&entry, &ancestors) {
+                Ok(Inspected::SubDirectory(followed_link)) => {
+                    // le lien à garder est choisi ensuite par `sum_sizes`
+                    if let Some((id, link)) = followed_link {
+                        shared_inodes.push((path.clone(), id));
+                        self.followed_links.lock().unwrap().insert(path.clone(), link);
+                    }
+                    sub_directories.push((path, ignored));
+                }
+                Ok(Inspected::Node(node, shared_inode)) => {
+                    if let Some(id) = shared_inode {
+                        shared_inodes.push((path.clone(), id));
+                    }
+                    nodes.push((path, node));
+                }
+                Err(error) => {
+                    let node = self.record_error(DuError::io(&path, error));
+                    nodes.push((path, node));
+                }
+            }
+        }
+
+        // Appels récursifs pour les sous-répertoires
+        sub_directories.par_iter().for_each(|(sub_directory, ignored)| {
+            let ignores = self.ignores_below(&ignores, sub_directory, *ignored);
+            self.scan_directory(sub_directory, ancestors.clone(), ignores)
+        });
+
+        {
+            let mut map = self.map.lock().unwrap();
+            for (sub_directory, ignored) in &sub_directories {
+                let empty = matches!(
+                    map.get(sub_directory),
+                    Some(EntryNode::Directory { children, .. }) if children.is_empty()
+                );
+                if empty && self.prunes_if_empty(sub_directory, *ignored) {
+                    map.remove(sub_directory);
+                    children.retain(|child| child != sub_directory);
+                }
+            }
         }
+
+        let own_disk_size = self.own_disk_size(&own_metadata, &ignores);
+
+        // ordre stable, indépendant de l'ordre renvoyé par read_dir
+        children.sort();
+        self.shared_inodes.lock().unwrap().extend(shared_inodes);
+        let mut map = self.map.lock().unwrap();
+        map.extend(nodes);
+        map.insert(
+            directory.to_path_buf(),
+            EntryNode::Directory {
+                apparent_size: Size(0),
+                disk_size: own_disk_size,
+                children,
+                modified: own_metadata.modified().ok(),
+            },
+        );
     }
-    
 
-    pub fn files(&self, path: PathBuf) -> Vec<PathBuf> {
-        let mut vec: Vec<PathBuf> = vec![];
-        if let Ok(paths) = fs::read_dir(path) {
-            for entry in paths {
-                if let Ok(entry) = entry {
-                    if entry.file_type().map_or(false, |t| t.is_file()) {
-                        let entry = entry.path();
-                        vec.push(PathBuf::from(entry));
-                        }
+    // Variante séquentielle de `scan_directory` pour les sorties en flux :
+    // chaque entrée est transmise à `sink` dès qu'elle est connue, et un
+    // répertoire après tout son contenu, avec ses tailles cumulées et sans
+    // liste d'enfants. La mémoire n'est pas constante : elle croît avec les
+    // entrées des répertoires en cours de parcours, lues en entier pour être
+    // triées, et avec `seen`, qui garde un identifiant par inœud à plusieurs
+    // liens physiques (ou atteint par un lien suivi) déjà rencontré. `seen`
+    // joue le rôle qu'il a dans `sum_sizes`, l'ordre trié des entrées étant
+    // le même.
+    // Renvoie les tailles cumulées du répertoire, ou None s'il n'apparaît pas
+    // dans la sortie (rien d'ignoré dedans en mode OnlyIgnored, ou rien
+    // d'inclus avec --include).
+    fn stream_directory(
+        &self,
+        directory: &Path,
+        depth: usize,
+        mut ancestors: Vec<InodeId>,
+        ignores: &IgnoreStack,
+        seen: &mut HashSet<InodeId>,
+        sink: &mut dyn FnMut(&Path, usize, &EntryNode) -> Result<()>,
+    ) -> Result<Option<(Size, Size)>> {
+        let read_dir = fs::metadata(directory).and_then(|metadata| Ok((metadata, fs::read_dir(directory)?)));
+        let (own_metadata, read_dir) = match read_dir {
+            Ok(read_dir) => read_dir,
+            Err(error) => {
+                let node = self.record_error(DuError::io(directory, error));
+                sink(directory, depth, &node)?;
+                return Ok(Some((Size(0), Size(0))));
+            }
+        };
+        ancestors.extend(inode_id(&own_metadata));
+
+        // même ordre que les enfants dans l'arbre
+        let mut entries = vec![];
+        for entry in read_dir {
+            match entry {
+                Ok(entry) => entries.push(entry),
+                Err(error) => {
+                    self.record_error(DuError::io(directory, error));
+                }
+            }
+        }
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let (mut apparent, mut disk) = (Size(0), Size(0));
+        let mut is_empty = true;
+        for entry in entries {
+            let Some(ignored) = self.admit(&entry, ignores) else {
+                continue;
+            };
+            let path = entry.path();
+            let node = match self.inspect_entry(&entry, &ancestors) {
+                // comme dans `sum_sizes`, seul le premier lien vers un répertoire est suivi
+                Ok(Inspected::SubDirectory(Some((id, link)))) if !seen.insert(id) => link,
+                Ok(Inspected::SubDirectory(_)) => {
+                    let ignores = self.ignores_below(ignores, &path, ignored);
+                    let sizes = self.stream_directory(&path, depth + 1, ancestors.clone(), &ignores, seen, sink)?;
+                    if let Some((child_apparent, child_disk)) = sizes {
+                        apparent += child_apparent;
+                        disk += child_disk;
+                        is_empty = false;
                     }
+                    continue;
                 }
+                Ok(Inspected::Node(mut node, shared_inode)) => {
+                    if let (EntryNode::File { counted_elsewhere, .. }, Some(id)) = (&mut node, shared_inode) {
+                        *counted_elsewhere = !self.options.count_links && !seen.insert(id);
+                    }
+                    node
+                }
+                Err(error) => self.record_error(DuError::io(&path, error)),
+            };
+            if !matches!(node, EntryNode::File { counted_elsewhere: true, .. }) {
+                apparent += node.apparent_size();
+                disk += node.disk_size();
             }
-        vec
+            sink(&path, depth + 1, &node)?;
+            is_empty = false;
+        }
+
+        if depth > 0 && is_empty && self.prunes_if_empty(directory, ignores.inside_ignored()) {
+            return Ok(None);
+        }
+        disk += self.own_disk_size(&own_metadata, ignores);
+        let node = EntryNode::Directory {
+            apparent_size: apparent,
+            disk_size: disk,
+            children: vec![],
+            modified: own_metadata.modified().ok(),
+        };
+        sink(directory, depth, &node)?;
+        Ok(Some((apparent, disk)))
+    }
+
+    // Parcours de `root` encore vide, sans vérifier la racine
+    fn new(root: &Path, canonical_root: PathBuf, root_device: Option<u64>, options: ScanOptions) -> Scanner {
+        Scanner {
+            options,
+            root: root.to_path_buf(),
+            root_device,
+            canonical_root,
+            map: Mutex::new(HashMap::new()), // stocker les nœuds de l'arbre
+            shared_inodes: Mutex::new(HashMap::new()),
+            followed_links: Mutex::new(HashMap::new()),
+            errors: Mutex::new(vec![]),
+        }
+    }
+
+    // Prépare le parcours de `root` : vérifie la racine et charge les règles
+    // d'exclusion qui s'y appliquent
+    fn open(root: &Path, options: &ScanOptions) -> Result<(Scanner, IgnoreStack)> {
+        // une racine absente ou illisible est signalée avec la cause exacte
+        let metadata = fs::metadata(root).map_err(|e| DuError::io(root, e))?;
+        // Verifier si root est un repertoire , sinon afficher une erreur
+        if !metadata.is_dir() {
+            return Err(DuError::NotADirectory(root.to_path_buf()));
+        }
+        let canonical_root = fs::canonicalize(root).map_err(|e| DuError::io(root, e))?;
+        let scanner = Scanner::new(root, canonical_root, device_id(&metadata), options.clone());
+        let ignores = match options.ignore_mode {
+            IgnoreMode::Off => IgnoreStack::default(),
+            _ => IgnoreStack::for_root(root, &mut scanner.errors.lock().unwrap()),
+        };
+        Ok((scanner, ignores))
+    }
+
+    // Nœud de la racine si c'est un lien symbolique que la politique interdit
+    // de suivre : l'arbre se réduit alors à ce lien
+    fn unfollowed_root(root: &Path, options: &ScanOptions) -> Result<Option<EntryNode>> {
+        if options.symlinks != SymlinkPolicy::Never || !root.is_symlink() {
+            return Ok(None);
+        }
+        symlink_node(root, false).map(Some).map_err(|e| DuError::io(root, e))
+    }
+
+    // Erreurs rencontrées pendant le parcours, triées par chemin
+    fn into_errors(self) -> Vec<DuError> {
+        let mut errors = self.errors.into_inner().unwrap();
+        errors.sort_by(|a, b| a.path().cmp(b.path()));
+        errors
+    }
+}
+
+// Calcule les tailles des répertoires à partir de celles de leurs enfants.
+// Ce second passage est séquentiel et suit l'ordre trié des enfants : le premier
+// chemin vers un inœud partagé est toujours le même, quel que soit le nombre de
+// threads. Sans `count_links`, les chemins suivants sont marqués et ne comptent pas.
+// De même, un répertoire atteint par plusieurs liens suivis ne garde que le
+// contenu du premier ; les autres redeviennent les liens de `followed_links`.
+fn sum_sizes(
+    map: &mut HashMap<PathBuf, EntryNode>,
+    path: &Path,
+    shared_inodes: &HashMap<PathBuf, InodeId>,
+    followed_links: &HashMap<PathBuf, EntryNode>,
+    seen: &mut HashSet<InodeId>,
+    count_links: bool,
+) -> (Size, Size) {
+    let children = match map.get_mut(path) {
+        Some(EntryNode::File { apparent_size, disk_size, counted_elsewhere, .. }) => {
+            let already_counted = !count_links
+                && shared_inodes.get(path).is_some_and(|id| !seen.insert(*id));
+            if already_counted {
+                *counted_elsewhere = true;
+                return (Size(0), Size(0));
+            }
+            return (*apparent_size, *disk_size);
+        }
+        Some(EntryNode::Symlink { apparent_size, disk_size, .. }) => {
+            return (*apparent_size, *disk_size);
+        }
+        Some(EntryNode::MountPoint { .. }) | Some(EntryNode::Error { .. }) => return (Size(0), Size(0)),
+        Some(EntryNode::Directory { children, .. }) => children.clone(),
+        None => return (Size(0), Size(0)),
+    };
+    if let Some(link) = followed_links.get(path).filter(|_| !seen.insert(shared_inodes[path])) {
+        for child in &children {
+            remove_subtree(map, child);
+        }
+        map.insert(path.to_path_buf(), link.clone());
+        return (link.apparent_size(), link.disk_size());
+    }
+
+    let (apparent, disk) = children
+        .iter()
+        .map(|child| sum_sizes(map, child, shared_inodes, followed_links, seen, count_links))
+        .fold((Size(0), Size(0)), |(apparent, disk), (child_apparent, child_disk)| {
+            (apparent + child_apparent, disk + child_disk)
+        });
+
+    match map.get_mut(path) {
+        Some(EntryNode::Directory { apparent_size, disk_size, .. }) => {
+            *apparent_size = apparent;
+            *disk_size += disk;
+            (*apparent_size, *disk_size)
+        }
+        _ => unreachable!("path was a directory"),
+    }
+}
+
+// Retire `path` de la map, avec tout ce qu'il contient
+fn remove_subtree(map: &mut HashMap<PathBuf, EntryNode>, path: &Path) {
+    if let Some(EntryNode::Directory { children, .. }) = map.remove(path) {
+        for child in &children {
+            remove_subtree(map, child);
+        }
+    }
+}
+
+impl FileTree {
+    // creation d'un nouvel arbre de fichier
+    pub fn new(root: &Path) -> Result<Self> {
+        FileTree::with_options(root, &ScanOptions::default())
     }
 
+    // creation d'un nouvel arbre de fichier avec des options de parcours
+    pub fn with_options(root: &Path, options: &ScanOptions) -> Result<Self> {
+        let root = root.to_path_buf(); // mettre root en PathBuf pour faciliter la manipulation
+        if let Some(node) = Scanner::unfollowed_root(&root, options)? {
+            let map = HashMap::from([(root.clone(), node)]);
+            return Ok(FileTree { root, map, size_mode: SizeMode::default(), errors: vec![] });
+        }
+        let (scanner, ignores) = Scanner::open(&root, options)?;
+        // Un seul parcours du disque : toutes les requêtes suivantes
+        // sont servies depuis la map
+        scanner.scan_directory(&root, vec![], ignores);
+        let mut map = std::mem::take(&mut *scanner.map.lock().unwrap());
+        let shared_inodes = std::mem::take(&mut *scanner.shared_inodes.lock().unwrap());
+        let followed_links = std::mem::take(&mut *scanner.followed_links.lock().unwrap());
+        sum_sizes(&mut map, &root, &shared_inodes, &followed_links, &mut HashSet::new(), options.count_links);
+        // les erreurs sous un lien qui n'a finalement pas été suivi disparaissent avec lui
+        let dropped: Vec<&PathBuf> = followed_links
+            .keys()
+            .filter(|link| matches!(map.get(*link), Some(EntryNode::Symlink { .. })))
+            .collect();
+        let mut errors = scanner.into_errors();
+        errors.retain(|error| !dropped.iter().any(|link| error.path().starts_with(link)));
 
-    
-    pub fn doublons(&self, path: &Path) -> HashMap<String, Vec<PathBuf>> {
-        let mut seen_files: HashSet<Vec<u8>> = HashSet::new();
-        let mut duplicates: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        Ok(FileTree { root, map, size_mode: SizeMode::default(), errors }) // Retourne une nouvelle instance avec le chemin racine et la HashMap
+    }
 
-        self.check_duplicates_recursive(path, &mut seen_files, &mut duplicates);
+    // Parcourt `root` sans construire l'arbre : chaque entrée est transmise à
+    // `sink` avec sa profondeur (0 pour la racine) dès qu'elle est connue, un
+    // répertoire après son contenu avec ses tailles cumulées. Aucun arbre
+    // n'est gardé en mémoire (voir `stream_directory` pour ce qui l'est). Le
+    // parcours s'arrête à la première erreur de `sink` ; les entrées
+    // illisibles sont renvoyées.
+    pub fn stream(
+        root: &Path,
+        options: &ScanOptions,
+        sink: &mut dyn FnMut(&Path, usize, &EntryNode) -> Result<()>,
+    ) -> Result<Vec<DuError>> {
+        if let Some(node) = Scanner::unfollowed_root(root, options)? {
+            sink(root, 0, &node)?;
+            return Ok(vec![]);
+        }
+        let (scanner, ignores) = Scanner::open(root, options)?;
+        scanner.stream_directory(root, 0, vec![], &ignores, &mut HashSet::new(), sink)?;
+        Ok(scanner.into_errors())
+    }
 
-        duplicates
+    // Transmet les nœuds d'un arbre déjà construit à `sink`, dans le même
+    // ordre que `stream`
+    pub fn visit(&self, sink: &mut dyn FnMut(&Path, usize, &EntryNode) -> Result<()>) -> Result<()> {
+        self.visit_node(&self.root, 0, sink)
     }
 
-    fn check_duplicates_recursive(
+    fn visit_node(
         &self,
         path: &Path,
-        seen_files: &mut HashSet<Vec<u8>>,
-        duplicates: &mut HashMap<String, Vec<PathBuf>>,
-    ) {
-        if let Some(children) = self.get_children(path, false, None) {
+        depth: usize,
+        sink: &mut dyn FnMut(&Path, usize, &EntryNode) -> Result<()>,
+    ) -> Result<()> {
+        let Some(node) = self.map.get(path) else {
+            return Ok(());
+        };
+        if let EntryNode::Directory { children, .. } = node {
             for child in children {
-                match self.map.get(&child) {
-                    Some(EntryNode::File(_)) => {
-                        if let Ok(file_content) = fs::read(&child) {
-                            if seen_files.contains(&file_content) {
-                                let entry = duplicates.entry(hex_digest(Algorithm::MD5, &file_content))
-                                    .or_insert_with(Vec::new);
-                                entry.push(child);
-                            } else {
-                                seen_files.insert(file_content);
-                            }
-                        }
-                    }
-                    Some(EntryNode::Directory(_)) => {
-                        // Recursive call
-                        self.check_duplicates_recursive(&child, seen_files, duplicates);
-                    }
-                    None => (),
+                self.visit_node(child, depth + 1, sink)?;
+            }
+        }
+        sink(path, depth, node)
+    }
+
+    // Arbre construit à partir de nœuds dans l'état où les laisse le parcours :
+    // un répertoire ne porte que son propre espace disque et ses enfants triés.
+    // Les tailles sont cumulées ici ; les fichiers de `shared_inodes` qui
+    // partagent un inœud ne sont comptés qu'une fois.
+    pub fn from_entries(root: PathBuf, mut map: HashMap<PathBuf, EntryNode>, shared_inodes: HashMap<PathBuf, InodeId>) -> Self {
+        sum_sizes(&mut map, &root, &shared_inodes, &HashMap::new(), &mut HashSet::new(), false);
+        FileTree { root, map, size_mode: SizeMode::default(), errors: vec![] }
+    }
+
+    // Arbre construit à partir de nœuds déjà calculés (export rechargé) ;
+    // les tailles des répertoires ne sont pas recalculées
+    pub fn from_nodes(root: PathBuf, map: HashMap<PathBuf, EntryNode>, size_mode: SizeMode) -> Self {
+        FileTree { root, map, size_mode, errors: vec![] }
+    }
+
+    pub fn get_root(&self) -> &PathBuf {
+        &self.root
+    }
+
+    // Renvoie les chemins qui n'ont pas pu être lus pendant le parcours, triés
+    pub fn errors(&self) -> &[DuError] {
+        &self.errors
+    }
+
+    // Enfants de `path`, triés par taille décroissante puis par nom, ou par nom
+    // seul ; avec les tailles filtrées de matching_sizes, seuls les enfants
+    // qui y figurent sont gardés et triés selon cette taille
+    pub fn get_children(
+        &self,
+        path: &Path,
+        lexicographic_sort: bool,
+        matching: Option<&MatchingSizes>,
+    ) -> Option<Vec<PathBuf>> {
+        match self.map.get(path)? {
+            EntryNode::Directory { children, .. } => {
+                let mut children = children.clone();
+
+                // Apply filtering : un répertoire est gardé s'il contient un
+                // fichier correspondant, même indirectement
+                if let Some(matching) = matching {
+                    children.retain(|child| matching.contains_key(child));
+                }
+
+                // Apply sorting
+                if lexicographic_sort {
+                    children.sort();
+                } else {
+                    // avec un filtre, seule la taille du contenu correspondant
+                    // compte ; sans, celle que l'enfant ajoute au répertoire
+                    let size = |child: &PathBuf| match matching {
+                        Some(matching) => matching.get(child).copied(),
+                        None => self.counted_size(child),
+                    };
+                    // du plus gros au plus petit, à taille égale dans l'ordre des noms
+                    children.sort_by(|a, b| size(b).cmp(&size(a)).then_with(|| a.cmp(b)));
+                }
+                //return the children
+                Some(children)
+            }
+            // les autres nœuds n'ont pas d'enfants
+            _ => Some(vec![]),
+        }
+    }
+
+    // Choisit la taille (apparente ou sur disque) renvoyée par get_size
+    pub fn set_size_mode(&mut self, size_mode: SizeMode) {
+        self.size_mode = size_mode;
+    }
+
+    pub fn size_mode(&self) -> SizeMode {
+        self.size_mode
+    }
+
+    pub fn get_size(&self, path: &Path) -> Option<Size> {
+        self.map.get(path).map(|node| node.size(self.size_mode))
+    }
+
+    // Taille que `path` ajoute à son répertoire : nulle pour un fichier déjà
+    // compté par un autre lien physique, comme dans sum_sizes
+    pub fn counted_size(&self, path: &Path) -> Option<Size> {
+        match self.map.get(path)? {
+            EntryNode::File { counted_elsewhere: true, .. } => Some(Size(0)),
+            node => Some(node.size(self.size_mode)),
+        }
+    }
+
+    // Taille du contenu qui passe le filtre pour chaque entrée qui en contient,
+    // calculée en un seul parcours de l'arbre ; une entrée absente ne contient
+    // aucun fichier correspondant
+    pub fn matching_sizes(&self, filter: &EntryFilter) -> MatchingSizes {
+        let mut sizes = HashMap::new();
+        self.collect_matching_sizes(&self.root, filter, &mut sizes);
+        sizes
+    }
+
+    // Parcours postfixe de matching_sizes : renvoie la taille correspondante
+    // de `path`, après avoir ajouté à `sizes` celles de ses descendants
+    fn collect_matching_sizes(&self, path: &Path, filter: &EntryFilter, sizes: &mut MatchingSizes) -> Option<Size> {
+        let node = self.map.get(path)?;
+        let size = node.size(self.size_mode);
+        let matching = match node {
+            // un lien physique déjà compté ailleurs ne pèse rien ici non plus
+            EntryNode::File { counted_elsewhere, .. } if filter.accepts(path, size, node.modified()) => {
+                Some(if *counted_elsewhere { Size(0) } else { size })
+            }
+            EntryNode::Symlink { .. } if filter.accepts(path, size, node.modified()) => Some(size),
+            EntryNode::Directory { children, .. } => children
+                .iter()
+                .filter_map(|child| self.collect_matching_sizes(child, filter, sizes))
+                .reduce(|total, size| total + size),
+            _ => None,
+        }?;
+        sizes.insert(path.to_path_buf(), matching);
+        Some(matching)
+    }
+
+    // Taille apparente, quel que soit le mode choisi
+    pub fn get_apparent_size(&self, path: &Path) -> Option<Size> {
+        self.map.get(path).map(EntryNode::apparent_size)
+    }
+
+    pub fn files(&self, path: PathBuf) -> Vec<PathBuf> {
+        match self.map.get(&path) {
+            Some(EntryNode::Directory { children, .. }) => children
+                .iter()
+                .filter(|child| matches!(self.map.get(*child), Some(EntryNode::File { .. })))
+                .cloned()
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    // Renvoie les fichiers de l'arbre situés sous `path`, à n'importe quelle
+    // profondeur : ceux de `path` d'abord, puis ceux de chaque sous-répertoire
+    pub fn files_recursive(&self, path: &Path) -> Vec<PathBuf> {
+        let mut files = self.files(path.to_path_buf());
+        if let Some(EntryNode::Directory { children, .. }) = self.map.get(path) {
+            for child in children {
+                if matches!(self.map.get(child), Some(EntryNode::Directory { .. })) {
+                    files.extend(self.files_recursive(child));
                 }
             }
         }
+        files
     }
 }
 
 
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*; 
+    use crate::print_tree::ShowOptions;
     use std::fs::{self, File};
     use std::io::Write;
-    use std::env;
+    use tempfile::{tempdir, TempDir};
+
+    // Builds root/{big/{deep/leaf.txt, mid.txt}, small.txt} of 300, 200 and
+    // 100 bytes, shared by the tests of the output formats
+    pub(crate) fn sample_tree() -> (TempDir, FileTree) {
+        let temp_dir = create_temp_dir();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("big/deep")).unwrap();
+        create_temp_file(&root.join("big/deep"), "leaf.txt", &[0; 300]);
+        create_temp_file(&root.join("big"), "mid.txt", &[0; 200]);
+        create_temp_file(root, "small.txt", &[0; 100]);
+        let file_tree = FileTree::new(root).unwrap();
+        (temp_dir, file_tree)
+    }
+
+    // Runs `f` with the 8 MiB stack of the main thread rather than the 2 MiB
+    // of a test thread, for trees as deep as the loaders accept
+    pub(crate) fn with_main_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+        std::thread::scope(|scope| {
+            let thread = std::thread::Builder::new().stack_size(8 << 20);
+            thread.spawn_scoped(scope, f).unwrap().join().unwrap()
+        })
+    }
 
-    // Helper function to create a temporary directory and return its path
-    fn create_temp_dir() -> PathBuf {
-        let mut dir = env::temp_dir();
-        dir.push("test_dir");
-        fs::create_dir_all(&dir).expect("Failed to create temp dir");
-        dir
+    // Helper function to create a temporary directory, removed when dropped
+    fn create_temp_dir() -> TempDir {
+        tempdir().expect("Failed to create temp dir")
     }
 
     // Helper function to create a temporary file with content
@@ -230,58 +1008,13 @@ mod tests {
         file_path
     }
 
-    #[test]
-    fn test_doublons_with_content_comparison() {
-        // Create a temporary directory and files for testing
-        let temp_dir = create_temp_dir();
-        let file1_path = create_temp_file(&temp_dir, "file1.txt", b"content");
-        let file2_path = create_temp_file(&temp_dir, "file2.txt", b"content");
-        let file3_path = create_temp_file(&temp_dir, "file3.txt", b"different_content");
-
-        // Create a FileTree instance
-        let file_tree = FileTree::new(&temp_dir).expect("Failed to create FileTree");
-
-        // Test the doublons method
-        let duplicates = file_tree.doublons(&temp_dir);
-
-        // Verify the results
-        assert_eq!(duplicates.len(), 1);
-        assert!(duplicates.contains_key(&hex_digest(Algorithm::MD5, b"content")));
-
-        let duplicate_files = duplicates.get(&hex_digest(Algorithm::MD5, b"content")).unwrap();
-        assert_eq!(duplicate_files.len(), 2);
-        assert!(duplicate_files.contains(&file1_path));
-        assert!(duplicate_files.contains(&file2_path));
-        assert!(!duplicate_files.contains(&file3_path));
-    }
-
-    #[test]
-    fn test_doublons_without_content_comparison() {
-        // Create a temporary directory and files for testing
-        let temp_dir = create_temp_dir();
-        let file1_path = create_temp_file(&temp_dir, "file1.txt", b"content");
-        let file2_path = create_temp_file(&temp_dir, "file2.txt", b"different_content");
-
-        // Create a FileTree instance
-        let file_tree = FileTree::new(&temp_dir).expect("Failed to create FileTree");
-
-        // Test the doublons method
-        let duplicates = file_tree.doublons(&temp_dir);
-
-        // Verify the results
-        assert!(duplicates.is_empty());
-    }
- 
     #[test]
     fn test_create_file_tree_with_valid_directory() {
-        let root_path = Path::new("test_directory");
-        fs::create_dir(root_path).unwrap(); // Create a test directory
+        let temp_dir = create_temp_dir(); // Create a test directory
 
-        let file_tree_result = FileTree::new(root_path);
+        let file_tree_result = FileTree::new(temp_dir.path());
 
         assert!(file_tree_result.is_ok());
-
-        fs::remove_dir(root_path).unwrap(); // Remove the test directory
     }
 
     #[test]
@@ -290,79 +1023,608 @@ mod tests {
 
         let file_tree_result = FileTree::new(root_path);
 
-        assert!(file_tree_result.is_err());
+        assert!(matches!(file_tree_result, Err(DuError::Io(path, _)) if path == root_path));
+
+        let temp_dir = create_temp_dir();
+        let file_path = create_temp_file(temp_dir.path(), "file.txt", b"content");
+        let file_tree_result = FileTree::new(&file_path);
+
+        assert!(matches!(file_tree_result, Err(DuError::NotADirectory(path)) if path == file_path));
     }
 
     #[test]
     fn test_get_root() {
-        let root_path = Path::new("test_directory");
-        fs::create_dir(root_path).unwrap(); // Create a test directory
+        let temp_dir = create_temp_dir(); // Create a test directory
+        let root_path = temp_dir.path();
 
         let file_tree = FileTree::new(root_path).unwrap();
 
         assert_eq!(file_tree.get_root(), root_path);
-
-        fs::remove_dir(root_path).unwrap(); // Remove the test directory
     }
 
     #[test]
     fn test_get_size() {
-        let root_path = Path::new("test_directory");
-        fs::create_dir(root_path).unwrap(); // Create a test directory
+        let temp_dir = create_temp_dir(); // Create a test directory
+        let root_path = temp_dir.path();
 
         let file_tree = FileTree::new(root_path).unwrap();
 
         let size = file_tree.get_size(root_path);
         assert!(size.is_some());
-
-        fs::remove_dir(root_path).unwrap(); // Remove the test directory
     }
 
     #[test]
     fn test_get_files() {
-        let root_path = Path::new("test_directory");
-        fs::create_dir(root_path).unwrap(); // Create a test directory
+        let temp_dir = create_temp_dir(); // Create a test directory
+        let root_path = temp_dir.path();
         let file_path: PathBuf = root_path.join("test_file.txt");
         let file_path_2: PathBuf = root_path.join("test_file_2.txt");
 
         fs::File::create(&file_path).unwrap(); // Create a test file in the directory
         fs::File::create(&file_path_2).unwrap(); // Create a test file in the directory
+        fs::create_dir(root_path.join("test_dir")).unwrap(); // Directories are not files
 
         let file_tree = FileTree::new(root_path).unwrap();
 
         let files = file_tree.files(root_path.to_path_buf());
-        assert_eq!(files, vec![PathBuf::from("test_file_2.txt"), PathBuf::from("test_file.txt")]);
+        assert_eq!(files, vec![file_path, file_path_2]);
+    }
 
-        fs::remove_file(file_path).unwrap(); // Remove the test files
-        fs::remove_file(file_path_2).unwrap(); // Remove the test files
-        fs::remove_dir(root_path).unwrap(); // Remove the test directory
+    #[test]
+    fn test_get_files_recursive() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        fs::create_dir_all(root_path.join("a/b")).unwrap();
+        let deep = create_temp_file(&root_path.join("a/b"), "deep.txt", b"");
+        let top = create_temp_file(root_path, "top.txt", b"");
+        let nested = create_temp_file(&root_path.join("a"), "nested.txt", b"");
+
+        let file_tree = FileTree::new(root_path).unwrap();
+
+        // The files of a directory come before those of its sub-directories
+        assert_eq!(file_tree.files_recursive(root_path), vec![top, nested, deep]);
     }
 
     #[test]
     fn test_get_children() {
-        let root_path = Path::new("test_directory");
-        fs::create_dir(root_path).unwrap(); // Create a test directory
-        let file_path: PathBuf = root_path.join("test_file.txt");
-        let file_path_2: PathBuf = root_path.join("test_file_2.txt");
+        let temp_dir = create_temp_dir(); // Create a test directory
+        let root_path = temp_dir.path();
+        let file_path = create_temp_file(root_path, "test_file.txt", b"a");
+        let file_path_2 = create_temp_file(root_path, "test_file_2.txt", b"abc");
         let dir_path: PathBuf = root_path.join("test_dir");
-        let file_path_3: PathBuf = root_path.join("test_dir/test_file_2.txt");
-
-
-        fs::File::create(&file_path).unwrap(); // Create a test file in the directory
-        fs::File::create(&file_path_2).unwrap(); // Create a test file in the directory
         fs::create_dir(&dir_path).unwrap();
-        fs::File::create(&file_path_3).unwrap(); // Create a test file in the directory
+        create_temp_file(&dir_path, "test_file_2.txt", b"ab"); // Create a test file in the sub-directory
 
         let file_tree = FileTree::new(root_path).unwrap();
 
         let children = file_tree.get_children(root_path, false, None);
         assert!(children.is_some());
-        assert_eq!(children.unwrap(), vec![PathBuf::from("test_file.txt")]);
+        assert_eq!(children.unwrap(), vec![file_path_2.clone(), dir_path.clone(), file_path.clone()]);
+
+        // Entries of equal size keep the order of their names
+        let file_path_3 = create_temp_file(root_path, "other_file.txt", b"abc");
+        let file_tree = FileTree::new(root_path).unwrap();
+        let children = file_tree.get_children(root_path, false, None).unwrap();
+        assert_eq!(children, vec![file_path_3, file_path_2, dir_path, file_path]);
+    }
+
+    #[test]
+    fn test_extension_filter_keeps_matching_ancestors() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        let photos = root_path.join("photos/2023");
+        fs::create_dir_all(&photos).unwrap();
+        fs::create_dir(root_path.join("docs")).unwrap();
+        create_temp_file(&photos, "a.jpg", b"12345");
+        create_temp_file(&photos, "notes.txt", b"1234567890");
+        create_temp_file(&root_path.join("docs"), "report.txt", b"123");
+        let top = create_temp_file(root_path, "top.jpg", b"12");
+
+        let file_tree = FileTree::new(root_path).unwrap();
+
+        let filter = EntryFilter { file_extension: Some(".jpg"), ..Default::default() };
+        let matching = file_tree.matching_sizes(&filter);
+        let children = file_tree.get_children(root_path, false, Some(&matching)).unwrap();
+        assert_eq!(children, vec![root_path.join("photos"), top]);
+        let filter = EntryFilter { file_extension: Some("jpg"), ..Default::default() };
+        let matching = file_tree.matching_sizes(&filter);
+        let nested = file_tree.get_children(&photos, true, Some(&matching)).unwrap();
+        assert_eq!(nested, vec![photos.join("a.jpg")]);
+
+        assert_eq!(matching.get(root_path), Some(&Size(7)));
+        assert_eq!(matching.get(&photos), Some(&Size(5)));
+        assert_eq!(matching.get(&root_path.join("docs")), None);
+    }
+
+    #[test]
+    fn test_size_and_age_filters() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        fs::create_dir(root_path.join("old")).unwrap();
+        let old = create_temp_file(&root_path.join("old"), "archive.tar", b"1234567890");
+        let recent = create_temp_file(root_path, "recent.txt", b"123");
+        let a_year_ago = SystemTime::now() - std::time::Duration::from_secs(365 * 24 * 3600);
+        File::options().write(true).open(&old).unwrap().set_modified(a_year_ago).unwrap();
+
+        let file_tree = FileTree::new(root_path).unwrap();
+        let a_week_ago = SystemTime::now() - std::time::Duration::from_secs(7 * 24 * 3600);
+        assert!(file_tree.map[&old].modified().is_some_and(|modified| modified < a_week_ago));
+
+        let newer = EntryFilter { newer_than: Some(a_week_ago), ..Default::default() };
+        let matching = file_tree.matching_sizes(&newer);
+        assert_eq!(file_tree.get_children(root_path, false, Some(&matching)), Some(vec![recent.clone()]));
+        let older = EntryFilter { older_than: Some(a_week_ago), ..Default::default() };
+        let matching = file_tree.matching_sizes(&older);
+        assert_eq!(file_tree.get_children(root_path, false, Some(&matching)), Some(vec![root_path.join("old")]));
+        assert_eq!(matching.get(root_path), Some(&Size(10)));
+        let small = EntryFilter { max_size: Some(Size(5)), ..Default::default() };
+        assert_eq!(file_tree.matching_sizes(&small).get(root_path), Some(&Size(3)));
+    }
+
+    #[test]
+    fn test_excluded_directories_are_pruned() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        let modules = root_path.join("web/node_modules");
+        fs::create_dir_all(modules.join("pkg")).unwrap();
+        create_temp_file(&modules.join("pkg"), "index.js", b"1234567890");
+        let kept = create_temp_file(&root_path.join("web"), "app.js", b"123");
+        create_temp_file(root_path, "debug.log", b"12345");
+
+        let filter = PathFilter::new(&[], &["node_modules/".to_string(), "*.log".to_string()], false).unwrap();
+        let options = ScanOptions { filter, ..Default::default() };
+        let file_tree = FileTree::with_options(root_path, &options).unwrap();
+
+        // the excluded subtree was never walked
+        assert!(!file_tree.map.contains_key(&modules));
+        assert!(!file_tree.map.contains_key(&modules.join("pkg")));
+        assert!(!file_tree.map.contains_key(&root_path.join("debug.log")));
+        assert_eq!(file_tree.files_recursive(root_path), vec![kept]);
+        assert_eq!(file_tree.get_size(root_path), Some(Size(3)));
+    }
+
+    // Builds a git checkout with ignored build output and a re-included file
+    fn create_checkout() -> TempDir {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        fs::create_dir_all(root_path.join(".git")).unwrap();
+        fs::create_dir_all(root_path.join("target/debug")).unwrap();
+        fs::create_dir_all(root_path.join("src")).unwrap();
+        create_temp_file(root_path, ".gitignore", b"target/\n*.log\n");
+        create_temp_file(&root_path.join("src"), ".duignore", b"!keep.log\n");
+        create_temp_file(&root_path.join("target/debug"), "du", b"1234567890");
+        create_temp_file(&root_path.join("src"), "main.rs", b"12");
+        create_temp_file(&root_path.join("src"), "keep.log", b"123");
+        create_temp_file(root_path, "run.log", b"12345");
+        temp_dir
+    }
+
+    #[test]
+    fn test_ignore_files_are_respected() {
+        let temp_dir = create_checkout();
+        let root_path = temp_dir.path();
+        let options = ScanOptions { ignore_mode: IgnoreMode::Respect, ..Default::default() };
+        let file_tree = FileTree::with_options(root_path, &options).unwrap();
+
+        assert!(!file_tree.map.contains_key(&root_path.join("target")));
+        assert!(!file_tree.map.contains_key(&root_path.join("run.log")));
+        assert!(file_tree.map.contains_key(&root_path.join("src/keep.log")));
+        // the repository itself is skipped, as git does
+        assert!(!file_tree.map.contains_key(&root_path.join(".git")));
+        assert!(file_tree.errors().is_empty());
+    }
+
+    #[test]
+    fn test_only_ignored_bytes_are_reported() {
+        let temp_dir = create_checkout();
+        let root_path = temp_dir.path();
+        let options = ScanOptions { ignore_mode: IgnoreMode::OnlyIgnored, ..Default::default() };
+        let file_tree = FileTree::with_options(root_path, &options).unwrap();
+
+        assert_eq!(
+            file_tree.files_recursive(root_path),
+            vec![root_path.join("run.log"), root_path.join("target/debug/du")]
+        );
+        // src holds nothing ignored, .git is not ignored either
+        assert!(!file_tree.map.contains_key(&root_path.join("src")));
+        assert_eq!(file_tree.get_size(root_path), Some(Size(15)));
+    }
+
+    #[test]
+    fn test_include_prunes_directories_without_included_files() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        fs::create_dir_all(root_path.join("src")).unwrap();
+        fs::create_dir_all(root_path.join("target/debug")).unwrap();
+        fs::create_dir_all(root_path.join("docs/img")).unwrap();
+        create_temp_file(&root_path.join("src"), "main.rs", b"fn main() {}");
+        create_temp_file(&root_path.join("target/debug"), "du", b"binary");
+        create_temp_file(&root_path.join("docs"), "guide.md", b"# du");
+        let include = vec!["*.rs".to_string()];
+        let options = ScanOptions { filter: PathFilter::new(&include, &[], false).unwrap(), ..Default::default() };
+        let file_tree = FileTree::with_options(root_path, &options).unwrap();
+
+        let mut paths: Vec<_> = file_tree.map.keys().cloned().collect();
+        paths.sort();
+        let expected = vec![root_path.to_path_buf(), root_path.join("src"), root_path.join("src/main.rs")];
+        assert_eq!(paths, expected);
+
+        // streaming leaves out the same directories
+        let mut streamed = vec![];
+        FileTree::stream(root_path, &options, &mut |path, _, _| {
+            streamed.push(path.to_path_buf());
+            Ok(())
+        })
+        .unwrap();
+        streamed.sort();
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn test_sizes_are_computed_bottom_up() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        let sub_dir = root_path.join("a/b");
+        fs::create_dir_all(&sub_dir).unwrap();
+        let file_path = create_temp_file(&sub_dir, "deep.txt", b"12345");
+        create_temp_file(root_path, "top.txt", b"123");
+
+        let file_tree = FileTree::new(root_path).unwrap();
+
+        // Every entry of the tree is recorded during the scan
+        assert_eq!(file_tree.map.len(), 5);
+        assert_eq!(file_tree.get_size(&file_path), Some(Size(5)));
+        assert_eq!(file_tree.get_size(&sub_dir), Some(Size(5)));
+        assert_eq!(file_tree.get_size(&root_path.join("a")), Some(Size(5)));
+        assert_eq!(file_tree.get_size(root_path), Some(Size(8)));
+    }
+
+    #[test]
+    fn test_scan_does_not_depend_on_threads() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        for dir in 0..8 {
+            let sub_dir = root_path.join(format!("dir{dir}/sub"));
+            fs::create_dir_all(&sub_dir).unwrap();
+            for file in 0..20 {
+                create_temp_file(&sub_dir, &format!("file{file}"), &vec![0; dir * 100 + file]);
+            }
+        }
+
+        let scan = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let file_tree = pool.install(|| FileTree::new(root_path)).unwrap();
+            (file_tree.map.len(), file_tree.get_size(root_path), file_tree.render(&ShowOptions::default()))
+        };
+        let sequential = scan(1);
+        assert_eq!(sequential.0, 1 + 8 * 22);
+        assert_eq!(scan(4), sequential);
+    }
+
+    #[test]
+    fn test_apparent_and_disk_sizes() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        let file_path = create_temp_file(root_path, "small.txt", b"12345");
+
+        let mut file_tree = FileTree::new(root_path).unwrap();
+        let node = file_tree.map.get(&file_path).unwrap();
+        assert_eq!(node.apparent_size(), Size(5));
+        assert!(!node.is_sparse());
+        let file_disk_size = node.disk_size();
+        // the directory's own blocks are part of its disk usage
+        assert!(file_tree.map.get(root_path).unwrap().disk_size() >= file_disk_size);
+
+        file_tree.set_size_mode(SizeMode::Disk);
+        assert_eq!(file_tree.get_size(&file_path), Some(file_disk_size));
+        assert_eq!(file_tree.get_apparent_size(&file_path), Some(Size(5)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sparse_file_detection() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        let file_path = root_path.join("sparse.img");
+        // 64 MiB hole, nothing written
+        File::create(&file_path).unwrap().set_len(64 * 1024 * 1024).unwrap();
+
+        let file_tree = FileTree::new(root_path).unwrap();
+        let node = file_tree.map.get(&file_path).unwrap();
+
+        assert_eq!(node.apparent_size(), Size(64 * 1024 * 1024));
+        assert!(node.disk_size() < node.apparent_size());
+        assert!(node.is_sparse());
+    }
+
+    /// Tests that a small file stored without any block is not reported as sparse.
+    #[test]
+    fn test_small_inline_file_is_not_sparse() {
+        let file = |apparent_size: u64| EntryNode::File {
+            apparent_size: Size(apparent_size),
+            disk_size: Size(0),
+            counted_elsewhere: false,
+            modified: None,
+        };
+        assert!(!file(60).is_sparse());
+        assert!(!file(4095).is_sparse());
+        assert!(file(4096).is_sparse());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hard_links_counted_once() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        let sub_dir = root_path.join("b");
+        fs::create_dir(&sub_dir).unwrap();
+        let original = create_temp_file(root_path, "a.bin", &[0; 1000]);
+        let link = sub_dir.join("link.bin");
+        fs::hard_link(&original, &link).unwrap();
+
+        let file_tree = FileTree::new(root_path).unwrap();
+        assert_eq!(file_tree.get_size(root_path), Some(Size(1000)));
+        assert_eq!(file_tree.get_size(&sub_dir), Some(Size(0)));
+        assert!(matches!(file_tree.map.get(&link), Some(EntryNode::File { counted_elsewhere: true, .. })));
+        assert!(matches!(file_tree.map.get(&original), Some(EntryNode::File { counted_elsewhere: false, .. })));
+
+        let options = ScanOptions { count_links: true, ..Default::default() };
+        let file_tree = FileTree::with_options(root_path, &options).unwrap();
+        assert_eq!(file_tree.get_size(root_path), Some(Size(2000)));
+        assert_eq!(file_tree.get_size(&sub_dir), Some(Size(1000)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hard_links_sorted_by_counted_size() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        let original = create_temp_file(root_path, "a.bin", &[0; 1000]);
+        let link = root_path.join("b.bin");
+        fs::hard_link(&original, &link).unwrap();
+        let small = create_temp_file(root_path, "c.bin", &[0; 10]);
+
+        // The second name adds nothing and comes after the smaller file
+        let file_tree = FileTree::new(root_path).unwrap();
+        assert_eq!(file_tree.get_children(root_path, false, None).unwrap(), vec![original, small, link]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_are_not_followed_by_default() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        let real_dir = root_path.join("real");
+        fs::create_dir(&real_dir).unwrap();
+        create_temp_file(&real_dir, "data.bin", &[0; 1000]);
+        let link = root_path.join("link");
+        std::os::unix::fs::symlink(&real_dir, &link).unwrap();
+
+        let file_tree = FileTree::new(root_path).unwrap();
+
+        match file_tree.map.get(&link) {
+            Some(EntryNode::Symlink { target, is_loop, .. }) => {
+                assert_eq!(target, &real_dir);
+                assert!(!is_loop);
+            }
+            _ => panic!("link should be recorded as a symlink"),
+        }
+        assert!(!file_tree.map.contains_key(&link.join("data.bin")));
+        // the symlink itself only weighs the length of its target path
+        assert!(file_tree.get_apparent_size(root_path).unwrap().0 < 2000);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks_counts_target_once_and_stops_loops() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        let real_dir = root_path.join("real");
+        fs::create_dir(&real_dir).unwrap();
+        create_temp_file(&real_dir, "data.bin", &[0; 1000]);
+        std::os::unix::fs::symlink(&real_dir, root_path.join("link")).unwrap();
+        let loop_link = real_dir.join("back_to_root");
+        std::os::unix::fs::symlink(root_path, &loop_link).unwrap();
+
+        let options = ScanOptions { symlinks: SymlinkPolicy::Always, ..Default::default() };
+        let file_tree = FileTree::with_options(root_path, &options).unwrap();
+
+        // the linked directory is already walked as `real`, so the link is not
+        // followed; the unfollowed links weigh the length of their target path
+        assert!(!file_tree.map.contains_key(&root_path.join("link/data.bin")));
+        assert!(matches!(
+            file_tree.map.get(&root_path.join("link")),
+            Some(EntryNode::Symlink { is_loop: false, .. })
+        ));
+        let links_size = (root_path.as_os_str().len() + real_dir.as_os_str().len()) as u64;
+        assert_eq!(file_tree.get_apparent_size(root_path), Some(Size(1000 + links_size)));
+        assert!(matches!(
+            file_tree.map.get(&loop_link),
+            Some(EntryNode::Symlink { is_loop: true, .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_root_follows_policy() {
+        let temp_dir = create_temp_dir();
+        let real_dir = temp_dir.path().join("real");
+        fs::create_dir(&real_dir).unwrap();
+        let link = temp_dir.path().join("link");
+        std::os::unix::fs::symlink(&real_dir, &link).unwrap();
+
+        assert!(FileTree::new(&link).is_ok());
+        // never following links, the tree is the link itself
+        let options = ScanOptions { symlinks: SymlinkPolicy::Never, ..Default::default() };
+        let file_tree = FileTree::with_options(&link, &options).unwrap();
+        assert_eq!(file_tree.map.len(), 1);
+        assert!(matches!(file_tree.map.get(&link), Some(EntryNode::Symlink { target, .. }) if target == &real_dir));
+        let mut streamed = vec![];
+        FileTree::stream(&link, &options, &mut |path, depth, _| {
+            streamed.push((path.to_path_buf(), depth));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(streamed, vec![(link, 0)]);
+    }
+
+    /// Tests that a directory reached through several followed links is walked once.
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks_walks_shared_directory_once() {
+        let temp_dir = create_temp_dir();
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        create_temp_file(&outside, "data.bin", &[0; 1000]);
+        let root_path = &temp_dir.path().join("root");
+        fs::create_dir(root_path).unwrap();
+        for name in ["b", "a", "c"] {
+            std::os::unix::fs::symlink(&outside, root_path.join(name)).unwrap();
+        }
+
+        let options = ScanOptions { symlinks: SymlinkPolicy::Always, ..Default::default() };
+        let file_tree = FileTree::with_options(root_path, &options).unwrap();
+
+        // the first link in name order is walked, the others stay links
+        assert!(file_tree.map.contains_key(&root_path.join("a/data.bin")));
+        for name in ["b", "c"] {
+            assert!(matches!(
+                file_tree.map.get(&root_path.join(name)),
+                Some(EntryNode::Symlink { is_loop: false, .. })
+            ));
+        }
+        let links_size = 2 * outside.as_os_str().len() as u64;
+        assert_eq!(file_tree.get_apparent_size(root_path), Some(Size(1000 + links_size)));
+
+        // streaming makes the same choice
+        let mut streamed = vec![];
+        FileTree::stream(root_path, &options, &mut |path, _, node| {
+            streamed.push((path.to_path_buf(), matches!(node, EntryNode::Symlink { .. })));
+            Ok(())
+        })
+        .unwrap();
+        assert!(streamed.contains(&(root_path.join("a/data.bin"), false)));
+        assert!(streamed.contains(&(root_path.join("b"), true)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_followed_link_choice_does_not_depend_on_threads() {
+        let temp_dir = create_temp_dir();
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        create_temp_file(&outside, "data.bin", &[0; 1000]);
+        let root_path = &temp_dir.path().join("root");
+        // `a` takes longer to walk than `b`, but its link comes first in sorted order
+        fs::create_dir_all(root_path.join("a")).unwrap();
+        fs::create_dir_all(root_path.join("b")).unwrap();
+        for index in 0..500 {
+            create_temp_file(&root_path.join("a"), &format!("file{index}"), b"1");
+        }
+        std::os::unix::fs::symlink(&outside, root_path.join("a/zlink")).unwrap();
+        std::os::unix::fs::symlink(&outside, root_path.join("b/zlink")).unwrap();
+
+        let options = ScanOptions { symlinks: SymlinkPolicy::Always, ..Default::default() };
+        for threads in [1, 4] {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let file_tree = pool.install(|| FileTree::with_options(root_path, &options)).unwrap();
+            assert!(file_tree.map.contains_key(&root_path.join("a/zlink/data.bin")), "{threads} threads");
+            assert!(matches!(file_tree.map.get(&root_path.join("b/zlink")), Some(EntryNode::Symlink { .. })));
+            assert!(!file_tree.map.contains_key(&root_path.join("b/zlink/data.bin")));
+
+            // the stream keeps the same link, with the same sizes
+            let mut streamed = vec![];
+            FileTree::stream(root_path, &options, &mut |path, _, node| {
+                streamed.push((path.to_path_buf(), node.apparent_size(), node.disk_size()));
+                Ok(())
+            })
+            .unwrap();
+            let mut visited = vec![];
+            file_tree
+                .visit(&mut |path, _, node| {
+                    visited.push((path.to_path_buf(), node.apparent_size(), node.disk_size()));
+                    Ok(())
+                })
+                .unwrap();
+            assert_eq!(streamed, visited);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_one_file_system_marks_other_devices() {
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        fs::create_dir(root_path.join("local")).unwrap();
+        let options = ScanOptions { one_file_system: true, ..Default::default() };
+        let mut scanner = Scanner::new(root_path, root_path.to_path_buf(), None, options);
+        let metadata = fs::metadata(root_path.join("local")).unwrap();
+
+        // same device as the root: walked normally
+        scanner.root_device = device_id(&metadata);
+        assert!(scanner.mount_point(&root_path.join("local"), &metadata).is_none());
+
+        // any other device is a mount boundary
+        scanner.root_device = device_id(&metadata).map(|device| device + 1);
+        match scanner.mount_point(&root_path.join("local"), &metadata) {
+            Some(EntryNode::MountPoint { device, .. }) => assert_eq!(Some(device), device_id(&metadata)),
+            _ => panic!("expected a mount point"),
+        }
+
+        // a tree on a single filesystem is unaffected
+        let options = ScanOptions { one_file_system: true, ..Default::default() };
+        let file_tree = FileTree::with_options(root_path, &options).unwrap();
+        assert!(matches!(
+            file_tree.map.get(&root_path.join("local")),
+            Some(EntryNode::Directory { .. })
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mount_description_of_root() {
+        let description = mount_description(Path::new("/"));
+        assert!(description.is_some_and(|description| !description.is_empty()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unreadable_directory_is_recorded_and_scan_continues() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = create_temp_dir();
+        let root_path = temp_dir.path();
+        let locked = root_path.join("locked");
+        fs::create_dir(&locked).unwrap();
+        create_temp_file(&locked, "secret.txt", b"secret");
+        create_temp_file(root_path, "visible.txt", b"1234");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // root ignores permissions: nothing to check in that case
+        let readable_anyway = fs::read_dir(&locked).is_ok();
+
+        let file_tree = FileTree::new(root_path);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        let file_tree = file_tree.unwrap();
+
+        assert_eq!(file_tree.get_size(&root_path.join("visible.txt")), Some(Size(4)));
+        if !readable_anyway {
+            match file_tree.map.get(&locked) {
+                Some(EntryNode::Error { message }) => assert_eq!(message, "permission denied"),
+                _ => panic!("locked directory should be an error node"),
+            }
+            assert_eq!(file_tree.errors().len(), 1);
+            assert!(matches!(&file_tree.errors()[0], DuError::PermissionDenied(path) if *path == locked));
+            assert_eq!(file_tree.get_size(root_path), Some(Size(4)));
+        }
+    }
+
+    #[test]
+    fn test_record_error_for_vanished_entry() {
+        let scanner = Scanner::new(Path::new("."), PathBuf::from("."), None, ScanOptions::default());
+        let error = io::Error::from(io::ErrorKind::NotFound);
+        let node = scanner.record_error(DuError::io(Path::new("gone.txt"), error));
 
-        fs::remove_file(file_path_3).unwrap();
-        fs::remove_dir(dir_path).unwrap();
-        fs::remove_file(file_path).unwrap(); // Remove the test file
-        fs::remove_file(file_path_2).unwrap(); // Remove the test file
-        fs::remove_dir(root_path).unwrap(); // Remove the test directory
+        assert!(matches!(node, EntryNode::Error { .. }));
+        assert!(matches!(&scanner.errors.lock().unwrap()[0], DuError::Io(path, _) if path == Path::new("gone.txt")));
     }
 }
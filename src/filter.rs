@@ -0,0 +1,331 @@
+use crate::error::{DuError, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use regex::RegexSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Fichiers d'exclusion lus dans chaque répertoire ; à égalité, .duignore l'emporte
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".duignore"];
+
+// Une liste de motifs --include ou --exclude
+#[derive(Clone, Debug)]
+enum Patterns {
+    // `any` s'applique à toutes les entrées, `directories` aux motifs terminés
+    // par '/' qui ne désignent que des répertoires
+    Glob { any: GlobSet, directories: GlobSet },
+    Regex(RegexSet),
+}
+
+impl Patterns {
+    // Compile les motifs ; None si la liste est vide
+    fn new(patterns: &[String], regex: bool) -> Result<Option<Self>> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+        if regex {
+            let set = RegexSet::new(patterns)
+                .map_err(|error| DuError::InvalidPattern(patterns.join(" "), error.to_string()))?;
+            return Ok(Some(Patterns::Regex(set)));
+        }
+
+        let mut any = GlobSetBuilder::new();
+        let mut directories = GlobSetBuilder::new();
+        for pattern in patterns {
+            let (glob, directory_only) = match pattern.strip_suffix('/') {
+                Some(glob) => (glob, true),
+                None => (pattern.as_str(), false),
+            };
+            // comme dans un .gitignore, un motif qui commence par '/' est ancré à la
+            // racine du parcours, et un motif sans '/' s'applique à n'importe quelle profondeur
+            let glob = match glob.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if glob.contains('/') => glob.to_string(),
+                None => format!("**/{}", glob),
+            };
+            let glob = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .map_err(|error| DuError::InvalidPattern(pattern.clone(), error.kind().to_string()))?;
+            if directory_only {
+                directories.add(glob);
+            } else {
+                any.add(glob);
+            }
+        }
+        let build = |builder: GlobSetBuilder| {
+            builder
+                .build()
+                .map_err(|error| DuError::InvalidPattern(patterns.join(" "), error.to_string()))
+        };
+        Ok(Some(Patterns::Glob { any: build(any)?, directories: build(directories)? }))
+    }
+
+    // `relative` est le chemin de l'entrée relatif à la racine du parcours
+    fn is_match(&self, relative: &Path, is_dir: bool) -> bool {
+        match self {
+            Patterns::Glob { any, directories } => {
+                any.is_match(relative) || (is_dir && directories.is_match(relative))
+            }
+            Patterns::Regex(set) => set.is_match(&relative.to_string_lossy()),
+        }
+    }
+
+    // Vrai si l'entrée ou l'un de ses répertoires parents correspond à un motif
+    fn is_match_or_inside(&self, relative: &Path, is_dir: bool) -> bool {
+        self.is_match(relative, is_dir)
+            || relative
+                .ancestors()
+                .skip(1)
+                .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                .any(|ancestor| self.is_match(ancestor, true))
+    }
+}
+
+// Filtres --include / --exclude appliqués pendant le parcours
+#[derive(Clone, Debug, Default)]
+pub struct PathFilter {
+    include: Option<Patterns>,
+    exclude: Option<Patterns>,
+}
+
+impl PathFilter {
+    // Motifs glob (`**/*.log`, `target/`), ou expressions régulières si `regex`
+    pub fn new(include: &[String], exclude: &[String], regex: bool) -> Result<Self> {
+        Ok(PathFilter {
+            include: Patterns::new(include, regex)?,
+            exclude: Patterns::new(exclude, regex)?,
+        })
+    }
+
+    // Vrai si l'entrée `relative` ne doit pas apparaître dans l'arbre. Un
+    // répertoire exclu n'est pas parcouru du tout ; les répertoires sont toujours
+    // parcourus pour y chercher des fichiers inclus, et un fichier est inclus si
+    // lui-même ou l'un de ses répertoires parents correspond à un motif --include
+    pub fn excludes(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.exclude.as_ref().is_some_and(|exclude| exclude.is_match(relative, is_dir)) {
+            return true;
+        }
+        match &self.include {
+            Some(include) if !is_dir => !include.is_match_or_inside(relative, false),
+            _ => false,
+        }
+    }
+
+    // Vrai si le répertoire `relative` reste dans l'arbre même sans fichier
+    // inclus : pas de motif --include, ou lui-même ou l'un de ses parents
+    // correspond à un motif. Les autres ne sont parcourus que pour y chercher
+    // des fichiers inclus.
+    pub fn includes_directory(&self, relative: &Path) -> bool {
+        self.include.as_ref().is_none_or(|include| include.is_match_or_inside(relative, true))
+    }
+}
+
+// Prise en compte des fichiers .gitignore, .git/info/exclude et .duignore
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IgnoreMode {
+    // les fichiers d'exclusion ne sont pas lus
+    #[default]
+    Off,
+    // les entrées ignorées ne sont ni affichées ni parcourues
+    Respect,
+    // seules les entrées ignorées sont comptées
+    OnlyIgnored,
+}
+
+// Règles d'exclusion en vigueur dans un répertoire, de la plus lointaine à la
+// plus proche : celles d'un sous-répertoire l'emportent sur celles des parents,
+// y compris pour ré-inclure un fichier avec un motif '!'
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreStack {
+    // chemin canonique du répertoire, auquel les motifs sont relatifs
+    base: PathBuf,
+    matchers: Vec<Arc<Gitignore>>,
+    // le répertoire est lui-même ignoré, et donc tout ce qu'il contient
+    inside_ignored: bool,
+}
+
+// Lit les règles des fichiers `files` ; un fichier absent n'est pas une erreur,
+// et comme git on ignore les lignes invalides
+fn load_rules(base: &Path, files: &[PathBuf], errors: &mut Vec<DuError>) -> Option<Arc<Gitignore>> {
+    let mut builder = GitignoreBuilder::new(base);
+    for file in files {
+        match fs::read_to_string(file) {
+            Ok(text) => {
+                for line in text.lines() {
+                    let _ = builder.add_line(Some(file.clone()), line);
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => errors.push(DuError::io(file, error)),
+        }
+    }
+    builder.build().ok().filter(|rules| !rules.is_empty()).map(Arc::new)
+}
+
+impl IgnoreStack {
+    // Règles applicables à la racine du parcours : celles de .git/info/exclude et
+    // des répertoires parents jusqu'à la racine du dépôt git qui la contient, puis
+    // les siennes. Les fichiers illisibles sont ajoutés à `errors`
+    pub fn for_root(root: &Path, errors: &mut Vec<DuError>) -> Self {
+        let base = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let mut stack = IgnoreStack { base: base.clone(), ..Default::default() };
+
+        if let Some(repository) = base.ancestors().find(|dir| dir.join(".git").exists()) {
+            let exclude = repository.join(".git/info/exclude");
+            stack.matchers.extend(load_rules(repository, &[exclude], errors));
+            let parents: Vec<&Path> = base.ancestors().skip(1).take_while(|dir| dir.starts_with(repository)).collect();
+            for parent in parents.into_iter().rev() {
+                let files = IGNORE_FILES.map(|name| parent.join(name));
+                stack.matchers.extend(load_rules(parent, &files, errors));
+            }
+        }
+        let files = IGNORE_FILES.map(|name| root.join(name));
+        stack.matchers.extend(load_rules(&base, &files, errors));
+        stack
+    }
+
+    // Règles du sous-répertoire `directory`, qui ajoute les siennes à celles-ci
+    pub fn descend(&self, directory: &Path, ignored: bool, errors: &mut Vec<DuError>) -> Self {
+        let base = self.base.join(directory.file_name().unwrap_or_default());
+        let mut matchers = self.matchers.clone();
+        // inutile de lire les règles d'un répertoire dont tout le contenu est ignoré
+        if !ignored && !self.inside_ignored {
+            let files = IGNORE_FILES.map(|name| directory.join(name));
+            matchers.extend(load_rules(&base, &files, errors));
+        }
+        IgnoreStack { base, matchers, inside_ignored: ignored || self.inside_ignored }
+    }
+
+    // Vrai si l'entrée `path` de ce répertoire est ignorée
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.inside_ignored {
+            return true;
+        }
+        let path = self.base.join(path.file_name().unwrap_or_default());
+        for rules in self.matchers.iter().rev() {
+            match rules.matched(&path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => (),
+            }
+        }
+        false
+    }
+
+    pub fn inside_ignored(&self) -> bool {
+        self.inside_ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str], regex: bool) -> PathFilter {
+        let strings = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        PathFilter::new(&strings(include), &strings(exclude), regex).unwrap()
+    }
+
+    #[test]
+    fn test_exclude_globs() {
+        let filter = filter(&[], &["**/*.log", "target/", "node_modules"], false);
+
+        assert!(filter.excludes(Path::new("a/b/debug.log"), false));
+        assert!(filter.excludes(Path::new("debug.log"), false));
+        assert!(filter.excludes(Path::new("sub/target"), true));
+        // a trailing '/' only matches directories
+        assert!(!filter.excludes(Path::new("target"), false));
+        assert!(filter.excludes(Path::new("web/node_modules"), true));
+        assert!(!filter.excludes(Path::new("src/main.rs"), false));
+    }
+
+    /// Tests that a leading '/' anchors a pattern to the scan root.
+    #[test]
+    fn test_leading_slash_anchors_to_root() {
+        let filter = filter(&[], &["/build/", "/*.log"], false);
+
+        assert!(filter.excludes(Path::new("build"), true));
+        assert!(!filter.excludes(Path::new("src/build"), true));
+        assert!(filter.excludes(Path::new("debug.log"), false));
+        assert!(!filter.excludes(Path::new("logs/debug.log"), false));
+    }
+
+    #[test]
+    fn test_include_globs_keep_directories() {
+        let filter = filter(&["*.rs", "docs/"], &[], false);
+
+        assert!(!filter.excludes(Path::new("src/main.rs"), false));
+        assert!(filter.excludes(Path::new("src/notes.txt"), false));
+        assert!(!filter.excludes(Path::new("src"), true));
+        assert!(!filter.excludes(Path::new("docs/guide/intro.txt"), false));
+        // directories walked only to look for included files
+        assert!(!filter.includes_directory(Path::new("src")));
+        assert!(filter.includes_directory(Path::new("docs")));
+        assert!(filter.includes_directory(Path::new("docs/guide")));
+        assert!(PathFilter::default().includes_directory(Path::new("src")));
+    }
+
+    #[test]
+    fn test_regex_patterns() {
+        let filter = filter(&[], &[r"(^|/)\.git$", r"\.tmp$"], true);
+
+        assert!(filter.excludes(Path::new(".git"), true));
+        assert!(filter.excludes(Path::new("a/b.tmp"), false));
+        assert!(!filter.excludes(Path::new("a/.github"), true));
+    }
+
+    #[test]
+    fn test_nested_ignore_files_and_negation() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join(".git/info/exclude"), "*.bak\n").unwrap();
+        fs::write(root.join(".gitignore"), "*.log\ntarget/\n").unwrap();
+        fs::write(root.join(".duignore"), "vendor/\n").unwrap();
+        fs::write(root.join("sub/.gitignore"), "!keep.log\n").unwrap();
+
+        let mut errors = vec![];
+        let stack = IgnoreStack::for_root(root, &mut errors);
+        assert!(stack.is_ignored(&root.join("a.log"), false));
+        assert!(stack.is_ignored(&root.join("old.bak"), false));
+        assert!(stack.is_ignored(&root.join("target"), true));
+        assert!(!stack.is_ignored(&root.join("target"), false));
+        assert!(stack.is_ignored(&root.join("vendor"), true));
+        assert!(!stack.is_ignored(&root.join("main.rs"), false));
+
+        let sub = stack.descend(&root.join("sub"), false, &mut errors);
+        assert!(sub.is_ignored(&root.join("sub/other.log"), false));
+        assert!(!sub.is_ignored(&root.join("sub/keep.log"), false));
+
+        // everything below an ignored directory is ignored
+        let vendor = stack.descend(&root.join("vendor"), true, &mut errors);
+        assert!(vendor.is_ignored(&root.join("vendor/lib.rs"), false));
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_parent_ignore_files_apply_inside_the_repository() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("project/src")).unwrap();
+        fs::write(root.join(".gitignore"), "*.o\n").unwrap();
+
+        let stack = IgnoreStack::for_root(&root.join("project"), &mut vec![]);
+        let src = stack.descend(&root.join("project/src"), false, &mut vec![]);
+        assert!(src.is_ignored(&root.join("project/src/main.o"), false));
+        assert!(!src.is_ignored(&root.join("project/src/main.c"), false));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let error = PathFilter::new(&["a[".to_string()], &[], false).unwrap_err();
+        assert!(matches!(error, DuError::InvalidPattern(pattern, _) if pattern == "a["));
+        assert!(PathFilter::new(&[], &["(".to_string()], true).is_err());
+    }
+}
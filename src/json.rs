@@ -0,0 +1,477 @@
+// Export JSON de l'arbre complet, et rechargement d'un export dans un FileTree.
+//
+// Schéma, version 1 :
+//
+//   {
+//     "version": 1,
+//     "root": "/chemin/de/la/racine",
+//     "size_mode": "apparent" | "disk",
+//     "tree": <nœud de la racine>
+//   }
+//
+// Chaque nœud porte les champs :
+//   "path"           chemin complet de l'entrée
+//   "kind"           "file", "directory", "symlink", "mount_point" ou "error"
+//   "apparent_size"  taille apparente en octets (cumulée pour un répertoire)
+//   "disk_size"      espace alloué en octets (cumulé pour un répertoire)
+//   "file_count"     nombre de fichiers du sous-arbre (1 pour un fichier)
+//   "mtime"          date de modification en secondes depuis l'époque Unix,
+//                    absent si elle est inconnue
+//   "children"       nœuds enfants triés par chemin, pour un répertoire
+// et, selon le type, les champs facultatifs :
+//   "counted_elsewhere"     fichier dont la taille est comptée par un autre lien physique
+//   "target", "is_loop"     cible d'un lien symbolique, lien qui ramène vers un parent
+//   "device", "filesystem"  point de montage non traversé
+//   "message"               erreur de lecture
+//
+// Un champ facultatif absent vaut false ou null. Au sein d'une version, les
+// champs ne sont ni renommés ni retirés ; un changement incompatible incrémente
+// "version". Les chemins qui ne sont pas de l'UTF-8 valide sont convertis avec
+// des caractères de remplacement.
+
+use crate::error::{DuError, Result};
+use crate::file_tree::{EntryNode, FileTree, SizeMode};
+use crate::size::Size;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Version du schéma écrite dans chaque export
+pub const JSON_VERSION: u32 = 1;
+
+// Profondeur maximale, en répertoires sous la racine, d'un arbre rechargé :
+// l'insertion et les parcours de l'arbre sont récursifs
+pub const MAX_DEPTH: usize = 1024;
+
+#[derive(Serialize, Deserialize)]
+struct JsonTree {
+    version: u32,
+    root: String,
+    size_mode: JsonSizeMode,
+    tree: JsonNode,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum JsonSizeMode {
+    Apparent,
+    Disk,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum JsonKind {
+    File,
+    Directory,
+    Symlink,
+    MountPoint,
+    Error,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonNode {
+    path: String,
+    kind: JsonKind,
+    apparent_size: u64,
+    disk_size: u64,
+    file_count: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mtime: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<JsonNode>>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    counted_elsewhere: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    is_loop: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filesystem: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+// Secondes depuis l'époque Unix, négatives pour une date antérieure
+pub fn to_unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+pub fn from_unix_seconds(seconds: i64) -> SystemTime {
+    let offset = Duration::from_secs(seconds.unsigned_abs());
+    if seconds >= 0 {
+        UNIX_EPOCH + offset
+    } else {
+        UNIX_EPOCH - offset
+    }
+}
+
+impl JsonNode {
+    // Nœud sans champ propre à un type
+    fn new(path: &Path, kind: JsonKind, node: &EntryNode) -> Self {
+        JsonNode {
+            path: path.to_string_lossy().into_owned(),
+            kind,
+            apparent_size: node.apparent_size().0,
+            disk_size: node.disk_size().0,
+            file_count: 0,
+            mtime: node.modified().map(to_unix_seconds),
+            children: None,
+            counted_elsewhere: false,
+            target: None,
+            is_loop: false,
+            device: None,
+            filesystem: None,
+            message: None,
+        }
+    }
+}
+
+impl FileTree {
+    // Construit récursivement le nœud JSON de `path`
+    fn json_node(&self, path: &Path) -> Option<JsonNode> {
+        let node = self.map.get(path)?;
+        let json = match node {
+            EntryNode::File { counted_elsewhere, .. } => JsonNode {
+                file_count: 1,
+                counted_elsewhere: *counted_elsewhere,
+                ..JsonNode::new(path, JsonKind::File, node)
+            },
+            EntryNode::Directory { children, .. } => {
+                let children: Vec<JsonNode> = children.iter().filter_map(|child| self.json_node(child)).collect();
+                JsonNode {
+                    file_count: children.iter().map(|child| child.file_count).sum(),
+                    children: Some(children),
+                    ..JsonNode::new(path, JsonKind::Directory, node)
+                }
+            }
+            EntryNode::Symlink { target, is_loop, .. } => JsonNode {
+                target: Some(target.to_string_lossy().into_owned()),
+                is_loop: *is_loop,
+                ..JsonNode::new(path, JsonKind::Symlink, node)
+            },
+            EntryNode::MountPoint { device, filesystem } => JsonNode {
+                device: Some(*device),
+                filesystem: filesystem.clone(),
+                ..JsonNode::new(path, JsonKind::MountPoint, node)
+            },
+            EntryNode::Error { message } => JsonNode {
+                message: Some(message.clone()),
+                ..JsonNode::new(path, JsonKind::Error, node)
+            },
+        };
+        Some(json)
+    }
+
+    // Écrit l'arbre complet au format JSON décrit en tête de ce fichier
+    pub fn write_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        let root = self.get_root();
+        let tree = JsonTree {
+            version: JSON_VERSION,
+            root: root.to_string_lossy().into_owned(),
+            size_mode: match self.size_mode() {
+                SizeMode::Apparent => JsonSizeMode::Apparent,
+                SizeMode::Disk => JsonSizeMode::Disk,
+            },
+            tree: self.json_node(root).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?,
+        };
+        let mut writer = io::BufWriter::new(writer);
+        serde_json::to_writer(&mut writer, &tree)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+
+    // Recharge un arbre exporté par write_json ; les tailles enregistrées sont
+    // reprises telles quelles. Un export de ncdu, reconnu à son tableau de
+    // premier niveau, est confié à from_ncdu.
+    // Un arbre de plus de MAX_DEPTH répertoires sous la racine est refusé. Le
+    // texte est d'abord mesuré : chaque répertoire y ajoute deux niveaux
+    // d'imbrication ici, un en ncdu, et un document trop imbriqué est refusé
+    // avant d'être lu. En deçà, la limite de 128 niveaux de serde_json est
+    // levée et serde_stacker agrandit la pile au besoin.
+    pub fn load_json(file: &Path) -> Result<FileTree> {
+        let invalid = |error: serde_json::Error| DuError::InvalidInput(file.to_path_buf(), error.to_string());
+        let bytes = fs::read(file).map_err(|e| DuError::io(file, e))?;
+        if nesting_depth(&bytes) > 2 * MAX_DEPTH + 3 {
+            return Err(DuError::InvalidInput(
+                file.to_path_buf(),
+                format!("tree nested deeper than {MAX_DEPTH} directories"),
+            ));
+        }
+        let mut deserializer = serde_json::Deserializer::from_slice(&bytes);
+        deserializer.disable_recursion_limit();
+        let value = serde_json::Value::deserialize(serde_stacker::Deserializer::new(&mut deserializer)).map_err(invalid)?;
+        deserializer.end().map_err(invalid)?;
+        if value.is_array() {
+            return FileTree::from_ncdu(file, &value);
+        }
+        let tree = JsonTree::deserialize(serde_stacker::Deserializer::new(value)).map_err(invalid)?;
+        if tree.version != JSON_VERSION {
+            return Err(DuError::InvalidInput(
+                file.to_path_buf(),
+                format!("unsupported version {}, expected {}", tree.version, JSON_VERSION),
+            ));
+        }
+
+        // les chemins du fichier ne sont pas fiables : un enfant qui reprend le
+        // chemin de son parent ferait boucler le rendu
+        if tree.tree.path != tree.root {
+            return Err(DuError::InvalidInput(
+                file.to_path_buf(),
+                format!("tree path '{}' does not match root '{}'", tree.tree.path, tree.root),
+            ));
+        }
+        let mut map = HashMap::new();
+        insert_json_node(&mut map, tree.tree, 0).map_err(|reason| DuError::InvalidInput(file.to_path_buf(), reason))?;
+        let size_mode = match tree.size_mode {
+            JsonSizeMode::Apparent => SizeMode::Apparent,
+            JsonSizeMode::Disk => SizeMode::Disk,
+        };
+        Ok(FileTree::from_nodes(PathBuf::from(tree.root), map, size_mode))
+    }
+}
+
+// Imbrication maximale des tableaux et objets d'un document JSON, sans
+// compter les crochets et accolades des chaînes
+fn nesting_depth(bytes: &[u8]) -> usize {
+    let (mut depth, mut max_depth) = (0usize, 0);
+    let (mut in_string, mut escaped) = (false, false);
+    for &byte in bytes {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            _ if in_string => {}
+            b'[' | b'{' => {
+                depth += 1;
+                max_depth = max_depth.max(depth);
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max_depth
+}
+
+// Ajoute `json`, situé `depth` répertoires sous la racine, et ses descendants
+// à la map des nœuds ; chaque enfant doit être une entrée directe de son
+// parent, et chaque chemin n'apparaître qu'une fois
+fn insert_json_node(map: &mut HashMap<PathBuf, EntryNode>, json: JsonNode, depth: usize) -> std::result::Result<(), String> {
+    let path = PathBuf::from(&json.path);
+    if depth > MAX_DEPTH {
+        return Err(format!("'{}' is nested deeper than {MAX_DEPTH} directories", json.path));
+    }
+    if map.contains_key(&path) {
+        return Err(format!("duplicate entry '{}'", json.path));
+    }
+    let apparent_size = Size(json.apparent_size);
+    let disk_size = Size(json.disk_size);
+    let modified = json.mtime.map(from_unix_seconds);
+    let node = match json.kind {
+        JsonKind::File => EntryNode::File {
+            apparent_size,
+            disk_size,
+            counted_elsewhere: json.counted_elsewhere,
+            modified,
+        },
+        JsonKind::Directory => {
+            let children = json.children.unwrap_or_default();
+            let paths: Vec<PathBuf> = children.iter().map(|child| PathBuf::from(&child.path)).collect();
+            if let Some(stray) = paths.iter().find(|child| child.parent() != Some(path.as_path())) {
+                return Err(format!("'{}' is not an entry of '{}'", stray.display(), json.path));
+            }
+            for child in children {
+                insert_json_node(map, child, depth + 1)?;
+            }
+            EntryNode::Directory { apparent_size, disk_size, children: paths, modified }
+        }
+        JsonKind::Symlink => EntryNode::Symlink {
+            target: PathBuf::from(json.target.unwrap_or_default()),
+            apparent_size,
+            disk_size,
+            is_loop: json.is_loop,
+            modified,
+        },
+        JsonKind::MountPoint => EntryNode::MountPoint {
+            device: json.device.unwrap_or_default(),
+            filesystem: json.filesystem,
+        },
+        JsonKind::Error => EntryNode::Error { message: json.message.unwrap_or_default() },
+    };
+    map.insert(path, node);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_tree::tests::{sample_tree, with_main_stack};
+    use crate::print_tree::ShowOptions;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    /// Tests the fields written for the root, a directory and a file.
+    #[test]
+    fn test_json_schema() {
+        let (temp_dir, file_tree) = sample_tree();
+        let mut out = vec![];
+        file_tree.write_json(&mut out).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(value["version"], 1);
+        assert_eq!(value["root"], temp_dir.path().to_string_lossy().as_ref());
+        assert_eq!(value["size_mode"], "apparent");
+        let tree = &value["tree"];
+        assert_eq!(tree["kind"], "directory");
+        assert_eq!(tree["apparent_size"], 600);
+        assert_eq!(tree["file_count"], 3);
+        assert!(tree["mtime"].is_i64());
+        let big = &tree["children"][0];
+        assert_eq!(big["path"], temp_dir.path().join("big").to_string_lossy().as_ref());
+        assert_eq!(big["file_count"], 2);
+        assert_eq!(big["children"][1]["kind"], "file");
+        assert!(big["children"][1].get("children").is_none());
+    }
+
+    /// Tests that a saved tree loads back to the same tree.
+    #[test]
+    fn test_json_round_trip() {
+        let (temp_dir, file_tree) = sample_tree();
+        let saved = temp_dir.path().join("big/tree.json");
+        file_tree.write_json(File::create(&saved).unwrap()).unwrap();
+
+        let loaded = FileTree::load_json(&saved).unwrap();
+
+        assert_eq!(loaded.get_root(), file_tree.get_root());
+        assert_eq!(loaded.map.len(), file_tree.map.len());
+        assert_eq!(loaded.render(&ShowOptions::default()), file_tree.render(&ShowOptions::default()));
+        let file = temp_dir.path().join("big/deep/leaf.txt");
+        let seconds = |tree: &FileTree| tree.map[&file].modified().map(to_unix_seconds);
+        assert_eq!(seconds(&loaded), seconds(&file_tree));
+    }
+
+    /// Tests that a tree deeper than serde_json's default nesting limit loads back.
+    #[test]
+    fn test_json_round_trip_deep_tree() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path().join("root");
+        let deepest: PathBuf = (0..100).fold(root.clone(), |path, _| path.join("d"));
+        fs::create_dir_all(&deepest).unwrap();
+        fs::write(deepest.join("leaf.txt"), b"12345").unwrap();
+        let file_tree = FileTree::new(&root).unwrap();
+        let saved = temp_dir.path().join("tree.json");
+        file_tree.write_json(File::create(&saved).unwrap()).unwrap();
+
+        let loaded = FileTree::load_json(&saved).unwrap();
+
+        assert_eq!(loaded.map.len(), file_tree.map.len());
+        assert_eq!(loaded.get_size(&root), Some(Size(5)));
+        assert!(loaded.map.contains_key(&deepest.join("leaf.txt")));
+    }
+
+    /// Tests that a tree nested deeper than MAX_DEPTH is an input error, not a stack overflow.
+    #[test]
+    fn test_load_rejects_deep_trees() {
+        let temp_dir = tempdir().unwrap();
+        let saved = temp_dir.path().join("tree.json");
+        // Written as text: a Value this deep could not be built or dropped
+        let load = |depth: usize| {
+            let node = |path: &str, kind: &str| {
+                format!(r#"{{"path": "{path}", "kind": "{kind}", "apparent_size": 1, "disk_size": 1, "file_count": 1"#)
+            };
+            let mut tree = String::new();
+            let mut path = String::from("/r");
+            for _ in 0..depth {
+                tree += &node(&path, "directory");
+                tree += r#", "children": ["#;
+                path += "/d";
+            }
+            tree += &node(&path, "file");
+            tree += &"}]".repeat(depth);
+            tree += "}";
+            fs::write(&saved, format!(r#"{{"version": 1, "root": "/r", "size_mode": "apparent", "tree": {tree}}}"#)).unwrap();
+            FileTree::load_json(&saved)
+        };
+        let expect_too_deep = |result: Result<FileTree>| {
+            let error = result.err().unwrap();
+            assert!(matches!(&error, DuError::InvalidInput(..)));
+            assert!(error.to_string().contains("deeper than 1024 directories"), "{error}");
+        };
+
+        with_main_stack(|| {
+            assert_eq!(load(MAX_DEPTH).unwrap().map.len(), MAX_DEPTH + 1);
+            expect_too_deep(load(MAX_DEPTH + 1));
+        });
+        // Far deeper than the stack allows: refused before parsing
+        fs::write(&saved, r#"{"tree": "#.to_string() + &r#"{"children": ["#.repeat(30_000)).unwrap();
+        expect_too_deep(FileTree::load_json(&saved));
+    }
+
+    /// Tests that other format versions and invalid JSON are rejected.
+    #[test]
+    fn test_load_rejects_other_versions() {
+        let temp_dir = tempdir().unwrap();
+        let saved = temp_dir.path().join("tree.json");
+        fs::write(
+            &saved,
+            r#"{"version": 2, "root": "/", "size_mode": "disk",
+                "tree": {"path": "/", "kind": "directory", "apparent_size": 0, "disk_size": 0, "file_count": 0}}"#,
+        )
+        .unwrap();
+        assert!(matches!(FileTree::load_json(&saved), Err(DuError::InvalidInput(path, _)) if path == saved));
+
+        fs::write(&saved, "not json").unwrap();
+        assert!(matches!(FileTree::load_json(&saved), Err(DuError::InvalidInput(..))));
+    }
+
+    /// Tests that a saved tree whose paths do not nest is rejected.
+    #[test]
+    fn test_load_rejects_inconsistent_paths() {
+        let temp_dir = tempdir().unwrap();
+        let saved = temp_dir.path().join("tree.json");
+        let load = |root: &str, children: &str| {
+            let tree = format!(
+                r#"{{"version": 1, "root": "{root}", "size_mode": "apparent",
+                    "tree": {{"path": "/a", "kind": "directory", "apparent_size": 0, "disk_size": 0,
+                              "file_count": 0, "children": [{children}]}}}}"#
+            );
+            fs::write(&saved, tree).unwrap();
+            FileTree::load_json(&saved)
+        };
+        let entry = |path: &str| {
+            format!(r#"{{"path": "{path}", "kind": "file", "apparent_size": 1, "disk_size": 1, "file_count": 1}}"#)
+        };
+
+        assert!(load("/a", &entry("/a/x")).is_ok());
+        // A child repeating its parent's path, a child outside its parent,
+        // a duplicate and a root that does not match the tree
+        for (root, children) in [
+            ("/a", entry("/a")),
+            ("/a", entry("/b/x")),
+            ("/a", format!("{},{}", entry("/a/x"), entry("/a/x"))),
+            ("/b", entry("/a/x")),
+        ] {
+            assert!(matches!(load(root, &children), Err(DuError::InvalidInput(..))), "{children}");
+        }
+    }
+
+    /// Tests the conversion of dates to and from Unix seconds, before the epoch too.
+    #[test]
+    fn test_unix_seconds() {
+        let before = UNIX_EPOCH - Duration::from_secs(10);
+        assert_eq!(to_unix_seconds(before), -10);
+        assert_eq!(from_unix_seconds(-10), before);
+        assert_eq!(from_unix_seconds(to_unix_seconds(UNIX_EPOCH + Duration::from_secs(42))), UNIX_EPOCH + Duration::from_secs(42));
+    }
+}
@@ -1,14 +1,32 @@
 // Importe les modules locaux nécessaires
+mod duplicates;
+mod error;
 mod file_tree;
+mod filter;
+mod json;
+mod ncdu;
 mod print_tree;
+mod report;
 mod size;
+mod stream;
+mod svg;
 
 // Importe la macro Parser et Subcommand de la bibliothèque clap
-use clap::{Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 
 // Importe FileTree du module file_tree et les types Path et PathBuf du module std::path
-use file_tree::FileTree;
+use duplicates::HashAlgorithm;
+use error::DuError;
+use file_tree::{EntryFilter, FileTree, ScanOptions, SizeMode, SymlinkPolicy};
+use filter::{IgnoreMode, PathFilter};
+use print_tree::{count_of, ShowOptions};
+use size::{Size, SizeFormat, UnitBase};
+use stream::{RecordWriter, StreamFormat};
+use svg::SvgOptions;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
 
 // Définit la structure principale de la ligne de commande avec clap
 #[derive(Parser)]
@@ -24,7 +42,76 @@ struct Cli {
 enum Commands {
     /// Affiche l'arbre d'utilisation du disque pour le chemin donné
     #[command(name = "Usage")]
-    usage(UsageOptions),
+    Usage(UsageOptions),
+    /// Affiche les fichiers dupliqués sous le chemin donné
+    #[command(name = "duplicates")]
+    Duplicates(DuplicatesOptions),
+    /// Écrit un rapport de l'utilisation du disque à consulter hors du terminal
+    #[command(name = "report")]
+    Report(ReportOptions),
+}
+
+// Options d'écriture des tailles, communes aux sous-commandes
+#[derive(Args)]
+struct SizeFormatOptions {
+    /// Unités en puissances de 1000 (kB, MB...) plutôt que 1024 (KiB, MiB...)
+    #[arg(long)]
+    si: bool,
+    /// Affiche les tailles en octets, sans unité
+    #[arg(long, conflicts_with_all = ["si", "block_size"])]
+    bytes: bool,
+    /// Affiche les tailles en nombre de blocs de cette taille, arrondi au supérieur (ex. 1K, 1M)
+    #[arg(long, conflicts_with = "si")]
+    block_size: Option<String>,
+}
+
+impl SizeFormatOptions {
+    fn size_format(&self) -> error::Result<SizeFormat> {
+        if self.bytes {
+            return Ok(SizeFormat::bytes());
+        }
+        Ok(SizeFormat {
+            base: if self.si { UnitBase::Si } else { UnitBase::Iec },
+            block_size: parse_size(self.block_size.as_deref())?,
+            ..Default::default()
+        })
+    }
+}
+
+// Lit une taille donnée en option ; une taille invalide est signalée par
+// DuError::InvalidSize, avec son propre code de sortie
+fn parse_size(text: Option<&str>) -> error::Result<Option<Size>> {
+    text.map(|text| text.parse().map_err(|error| DuError::InvalidSize(text.to_string(), error)))
+        .transpose()
+}
+
+// Formats de sortie de la sous-commande "Usage"
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum OutputFormat {
+    // arbre lisible, filtré selon les options d'affichage
+    #[default]
+    Tree,
+    // arbre complet, au schéma versionné décrit dans json.rs
+    Json,
+    // export au format JSON de ncdu, lisible par `ncdu -f` (voir ncdu.rs)
+    Ncdu,
+    // image SVG d'une carte proportionnelle, limitée par --max-depth (voir svg.rs)
+    Svg,
+    // un objet JSON par ligne et par entrée, écrit pendant le parcours (voir stream.rs)
+    Ndjson,
+    // une ligne CSV par entrée, écrite pendant le parcours (voir stream.rs)
+    Csv,
+}
+
+impl OutputFormat {
+    // Format d'enregistrement des sorties en flux
+    fn stream_format(self) -> Option<StreamFormat> {
+        match self {
+            OutputFormat::Ndjson => Some(StreamFormat::Ndjson),
+            OutputFormat::Csv => Some(StreamFormat::Csv),
+            OutputFormat::Tree | OutputFormat::Json | OutputFormat::Ncdu | OutputFormat::Svg => None,
+        }
+    }
 }
 
 // Définit les options de la sous-commande "Usage"
@@ -33,25 +120,417 @@ struct UsageOptions {
     /// (par défaut '.')
     #[arg(long)]
     path: Option<PathBuf>,
+    /// Recharge un arbre exporté avec --format json, ou un export de ncdu, au lieu de parcourir le disque
+    #[arg(
+        long,
+        value_name = "FILE",
+        // les options du parcours n'ont plus d'effet sur un arbre déjà construit
+        conflicts_with_all = [
+            "path", "include", "exclude", "regex", "count_links", "symlinks",
+            "ignore_files", "only_ignored", "one_file_system", "threads",
+        ]
+    )]
+    load: Option<PathBuf>,
+    /// Format de sortie. ndjson et csv écrivent chaque entrée pendant le parcours sans
+    /// construire l'arbre ; la mémoire croît toutefois avec les entrées des répertoires
+    /// ouverts, triées avant d'être écrites, et avec les inœuds à plusieurs liens physiques
+    /// (tous les inœuds avec --symlinks always)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Tree)]
+    format: OutputFormat,
     #[arg(long)]
     lexicographic_sort: bool,
-    #[arg(long, short = 'e')]
+    /// N'affiche que les fichiers ayant cette extension (ex. '.jpg') et les
+    /// répertoires qui en contiennent
+    #[arg(long, short = 'e', visible_alias = "filter")]
     file_extension: Option<String>,
+    /// Regroupe sur une ligne les entrées plus petites que cette taille (ex. 10M, 1.5GB, 200KiB)
+    #[arg(long)]
+    min_size: Option<String>,
+    /// N'affiche que les fichiers d'au plus cette taille
+    #[arg(long)]
+    max_size: Option<String>,
+    /// N'affiche que les fichiers modifiés depuis moins de cette durée (ex. 7d)
+    #[arg(long, value_parser = parse_duration)]
+    newer_than: Option<Duration>,
+    /// N'affiche que les fichiers modifiés depuis plus de cette durée (ex. 1y)
+    #[arg(long, value_parser = parse_duration)]
+    older_than: Option<Duration>,
+    /// Affiche la taille apparente des fichiers (par défaut)
+    #[arg(long, conflicts_with = "disk_usage")]
+    apparent_size: bool,
+    /// Affiche l'espace réellement alloué sur le disque, comme GNU du
+    #[arg(long)]
+    disk_usage: bool,
+    /// Compte un fichier pour chacun de ses liens physiques
+    #[arg(long)]
+    count_links: bool,
+    /// Liens symboliques à suivre pendant le parcours
+    #[arg(long, value_enum, default_value_t = SymlinkPolicy::CommandLine)]
+    symlinks: SymlinkPolicy,
+    /// N'affiche que les entrées correspondant à ce motif glob (répétable)
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+    /// Ignore les entrées correspondant à ce motif glob, sans les parcourir (répétable)
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+    /// Interprète les motifs --include et --exclude comme des expressions régulières
+    #[arg(long)]
+    regex: bool,
+    /// Respecte les fichiers .gitignore, .git/info/exclude et .duignore, et écarte le répertoire .git
+    #[arg(long, conflicts_with = "only_ignored")]
+    ignore_files: bool,
+    /// N'affiche que ce que ces fichiers d'exclusion ignorent
+    #[arg(long)]
+    only_ignored: bool,
+    /// Ne traverse pas les points de montage
+    #[arg(long, short = 'x')]
+    one_file_system: bool,
+    /// N'affiche pas les entrées au-delà de cette profondeur
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// Nombre de threads pour le parcours (par défaut : un par cœur)
+    #[arg(long)]
+    threads: Option<usize>,
+    #[command(flatten)]
+    size_format: SizeFormatOptions,
+}
+
+impl UsageOptions {
+    // Première option de l'affichage en arbre qui serait ignorée par le format
+    // choisi : les autres formats écrivent toutes les entrées dans leur ordre,
+    // avec des tailles en octets ; seule l'image SVG s'arrête à --max-depth et
+    // écrit ses tailles selon --si, --bytes et --block-size
+    fn ignored_by_format(&self) -> Option<&'static str> {
+        if self.format == OutputFormat::Tree {
+            return None;
+        }
+        let svg = self.format == OutputFormat::Svg;
+        let size_format = &self.size_format;
+        [
+            ("--lexicographic-sort", self.lexicographic_sort),
+            ("--max-depth", self.max_depth.is_some() && !svg),
+            ("--si", size_format.si && !svg),
+            ("--bytes", size_format.bytes && !svg),
+            ("--block-size", size_format.block_size.is_some() && !svg),
+            ("--min-size", self.min_size.is_some()),
+            ("--max-size", self.max_size.is_some()),
+            ("--file-extension", self.file_extension.is_some()),
+            ("--newer-than", self.newer_than.is_some()),
+            ("--older-than", self.older_than.is_some()),
+        ]
+        .into_iter()
+        .find_map(|(flag, is_set)| is_set.then_some(flag))
+    }
 }
 
-// Fonction principale
-fn main() -> std::io::Result<()> {
+// Définit les options de la sous-commande "duplicates"
+#[derive(Parser)]
+struct DuplicatesOptions {
+    /// (par défaut '.')
+    path: Option<PathBuf>,
+    /// Vérifie chaque groupe octet par octet pour écarter les collisions de hachage
+    #[arg(long)]
+    check_for_collisions: bool,
+    /// Fonction de hachage utilisée pour les signatures
+    #[arg(long, value_enum, default_value_t = HashAlgorithm::Md5)]
+    hash: HashAlgorithm,
+    /// Nombre de threads pour le parcours et le hachage (par défaut : un par cœur)
+    #[arg(long)]
+    threads: Option<usize>,
+    #[command(flatten)]
+    size_format: SizeFormatOptions,
+}
+
+// Définit les options de la sous-commande "report"
+#[derive(Parser)]
+struct ReportOptions {
+    /// (par défaut '.')
+    #[arg(long)]
+    path: Option<PathBuf>,
+    /// Recharge un arbre exporté avec --format json, ou un export de ncdu, au lieu de parcourir le disque
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["path", "one_file_system", "threads"])]
+    load: Option<PathBuf>,
+    /// Écrit une carte proportionnelle interactive dans ce fichier HTML autonome
+    #[arg(long, value_name = "FILE")]
+    html: PathBuf,
+    /// Utilise l'espace réellement alloué sur le disque plutôt que la taille apparente
+    #[arg(long)]
+    disk_usage: bool,
+    /// Ne traverse pas les points de montage
+    #[arg(long, short = 'x')]
+    one_file_system: bool,
+    /// Nombre de threads pour le parcours (par défaut : un par cœur)
+    #[arg(long)]
+    threads: Option<usize>,
+    #[command(flatten)]
+    size_format: SizeFormatOptions,
+}
+
+// Lit une durée comme "30s", "15m", "12h", "7d", "2w", "6mo", "1y"
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid duration '{}'", text))?;
+    let seconds: u64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 24 * 3600,
+        "w" => 7 * 24 * 3600,
+        "mo" => 30 * 24 * 3600,
+        "y" => 365 * 24 * 3600,
+        _ => return Err(format!("unknown duration unit '{}', expected s, m, h, d, w, mo or y", unit)),
+    };
+    // une durée trop grande pour Duration est refusée plutôt que de paniquer
+    let total = number * seconds as f64;
+    if !total.is_finite() || total < 0.0 {
+        return Err("duration out of range".to_string());
+    }
+    Duration::try_from_secs_f64(total).map_err(|_| "duration out of range".to_string())
+}
+
+// Date située `age` avant maintenant
+fn ago(age: Duration) -> SystemTime {
+    SystemTime::now().checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+// Fixe la taille du pool de threads de rayon utilisé pour le parcours et le hachage
+fn configure_threads(threads: Option<usize>) {
+    if let Some(threads) = threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("thread pool already initialised");
+    }
+}
+
+// Erreur d'écriture sur la sortie standard, désignée par « - »
+fn stdout_error(error: std::io::Error) -> DuError {
+    DuError::Write(PathBuf::from("-"), error)
+}
+
+// Affiche sur la sortie d'erreur les chemins illisibles et leur nombre ;
+// le code de retour est celui de l'erreur la plus grave rencontrée
+fn report_errors<'a>(errors: impl IntoIterator<Item = &'a DuError>) -> ExitCode {
+    let mut count = 0;
+    let mut code = 0;
+    for error in errors {
+        eprintln!("du: {}", error);
+        count += 1;
+        code = code.max(error.exit_code());
+    }
+    if count == 0 {
+        return ExitCode::SUCCESS;
+    }
+    eprintln!("du: {} could not be read", count_of(count, "path", "paths"));
+    ExitCode::from(code)
+}
+
+// Fonction principale : une erreur fatale est affichée avec le code de retour
+// propre à sa variante
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("du: {}", error);
+            ExitCode::from(error.exit_code())
+        }
+    }
+}
+
+fn run() -> error::Result<ExitCode> {
     // Parse les arguments de ligne de commande
     let cli = Cli::parse();
 
     // Effectue des actions en fonction de la sous-commande spécifiée
     match &cli.command {
-        Commands::usage(usage_options) => {
+        Commands::Usage(usage_options) => {
+            // clap ne sait pas interdire une option pour une seule valeur de --format
+            if let Some(flag) = usage_options.ignored_by_format() {
+                let format = usage_options.format.to_possible_value().expect("formats are not skipped");
+                let message = format!("the argument '{}' cannot be used with '--format {}'", flag, format.get_name());
+                Cli::command().error(ErrorKind::ArgumentConflict, message).exit();
+            }
             let path = usage_options.path.as_deref().unwrap_or(Path::new("."));
-            let lexicographic_sort = usage_options.lexicographic_sort;
-            let file_extension = usage_options.file_extension.as_deref();
-            FileTree::new(path)?.show(lexicographic_sort, file_extension);
+            let show_options = ShowOptions {
+                lexicographic_sort: usage_options.lexicographic_sort,
+                filter: EntryFilter {
+                    file_extension: usage_options.file_extension.as_deref(),
+                    max_size: parse_size(usage_options.max_size.as_deref())?,
+                    newer_than: usage_options.newer_than.map(ago),
+                    older_than: usage_options.older_than.map(ago),
+                },
+                min_size: parse_size(usage_options.min_size.as_deref())?,
+                size_format: usage_options.size_format.size_format()?,
+                max_depth: usage_options.max_depth,
+            };
+            configure_threads(usage_options.threads);
+            let scan_options = ScanOptions {
+                count_links: usage_options.count_links,
+                symlinks: usage_options.symlinks,
+                one_file_system: usage_options.one_file_system,
+                filter: PathFilter::new(&usage_options.include, &usage_options.exclude, usage_options.regex)?,
+                ignore_mode: if usage_options.only_ignored {
+                    IgnoreMode::OnlyIgnored
+                } else if usage_options.ignore_files {
+                    IgnoreMode::Respect
+                } else {
+                    IgnoreMode::Off
+                },
+            };
+            // en flux, l'arbre n'est jamais construit en mémoire
+            if let Some(format) = usage_options.format.stream_format() {
+                let mut writer = RecordWriter::new(format, std::io::stdout().lock()).map_err(stdout_error)?;
+                let mut sink = |path: &Path, depth, node: &_| writer.write(path, depth, node).map_err(stdout_error);
+                let errors = match &usage_options.load {
+                    Some(saved) => {
+                        let file_tree = FileTree::load_json(saved)?;
+                        file_tree.visit(&mut sink)?;
+                        vec![]
+                    }
+                    None => FileTree::stream(path, &scan_options, &mut sink)?,
+                };
+                writer.finish().map_err(stdout_error)?;
+                return Ok(report_errors(&errors));
+            }
+            let mut file_tree = match &usage_options.load {
+                Some(saved) => FileTree::load_json(saved)?,
+                None => FileTree::with_options(path, &scan_options)?,
+            };
+            if usage_options.disk_usage {
+                file_tree.set_size_mode(SizeMode::Disk);
+            } else if usage_options.apparent_size {
+                file_tree.set_size_mode(SizeMode::Apparent);
+            }
+            match usage_options.format {
+                OutputFormat::Tree => file_tree.show(&show_options),
+                OutputFormat::Json => file_tree.write_json(std::io::stdout().lock()).map_err(stdout_error)?,
+                OutputFormat::Ncdu => file_tree.write_ncdu(std::io::stdout().lock()).map_err(stdout_error)?,
+                OutputFormat::Svg => {
+                    let svg_options = SvgOptions {
+                        max_depth: show_options.max_depth,
+                        size_format: show_options.size_format,
+                        ..Default::default()
+                    };
+                    file_tree.write_svg(std::io::stdout().lock(), &svg_options).map_err(stdout_error)?
+                }
+                OutputFormat::Ndjson | OutputFormat::Csv => unreachable!("streamed above"),
+            }
+            Ok(report_errors(file_tree.errors()))
+        }
+        Commands::Duplicates(duplicates_options) => {
+            let path = duplicates_options.path.as_deref().unwrap_or(Path::new("."));
+            configure_threads(duplicates_options.threads);
+            let file_tree = FileTree::new(path)?;
+            let failures = file_tree.show_duplicates(
+                path,
+                duplicates_options.hash,
+                duplicates_options.check_for_collisions,
+                duplicates_options.size_format.size_format()?,
+            );
+            Ok(report_errors(file_tree.errors().iter().chain(&failures)))
         }
+        Commands::Report(report_options) => {
+            let path = report_options.path.as_deref().unwrap_or(Path::new("."));
+            configure_threads(report_options.threads);
+            let scan_options = ScanOptions { one_file_system: report_options.one_file_system, ..Default::default() };
+            let mut file_tree = match &report_options.load {
+                Some(saved) => FileTree::load_json(saved)?,
+                None => FileTree::with_options(path, &scan_options)?,
+            };
+            if report_options.disk_usage {
+                file_tree.set_size_mode(SizeMode::Disk);
+            }
+            let html = &report_options.html;
+            let write_error = |e| DuError::Write(html.clone(), e);
+            let file = std::fs::File::create(html).map_err(write_error)?;
+            file_tree
+                .write_html_report(file, &report_options.size_format.size_format()?)
+                .map_err(write_error)?;
+            Ok(report_errors(file_tree.errors()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 24 * 3600)));
+        assert_eq!(parse_duration("1y"), Ok(Duration::from_secs(365 * 24 * 3600)));
+        assert_eq!(parse_duration("90m"), Ok(Duration::from_secs(90 * 60)));
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("d").is_err());
+    }
+
+    /// Tests that size options report InvalidSize with its own exit code.
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size(Some("10M")).unwrap(), Some(Size(10 * 1024 * 1024)));
+        assert_eq!(parse_size(None).unwrap(), None);
+        let error = parse_size(Some("10Q")).unwrap_err();
+        assert!(matches!(&error, DuError::InvalidSize(text, _) if text == "10Q"));
+        assert_eq!(error.exit_code(), 9);
     }
-    Ok(())
-}
\ No newline at end of file
+
+    /// Tests that a duration too large for Duration is an error, not a panic.
+    #[test]
+    fn test_parse_duration_out_of_range() {
+        let error = Err("duration out of range".to_string());
+        assert_eq!(parse_duration("99999999999999999999y"), error);
+        assert_eq!(parse_duration(&format!("{}s", "9".repeat(400))), error);
+    }
+
+    /// Tests that the tree-only options are rejected by the other formats.
+    #[test]
+    fn test_export_formats_reject_tree_options() {
+        let usage = |args: &[&str]| match Cli::parse_from(["du", "Usage"].iter().chain(args)).command {
+            Commands::Usage(usage_options) => usage_options,
+            _ => unreachable!(),
+        };
+        assert_eq!(usage(&["--format", "csv", "--max-depth", "2"]).ignored_by_format(), Some("--max-depth"));
+        assert_eq!(usage(&["--format", "ndjson", "-e", "jpg"]).ignored_by_format(), Some("--file-extension"));
+        assert_eq!(usage(&["--format", "ndjson", "--exclude", "*.log"]).ignored_by_format(), None);
+        assert_eq!(usage(&["--format", "tree", "--max-depth", "2"]).ignored_by_format(), None);
+        assert_eq!(usage(&["--format", "json", "--max-depth", "2"]).ignored_by_format(), Some("--max-depth"));
+        assert_eq!(usage(&["--format", "ncdu", "--min-size", "1M"]).ignored_by_format(), Some("--min-size"));
+        assert_eq!(usage(&["--format", "svg", "--newer-than", "7d"]).ignored_by_format(), Some("--newer-than"));
+        assert_eq!(usage(&["--format", "svg", "--max-depth", "2"]).ignored_by_format(), None);
+        assert_eq!(usage(&["--format", "csv", "--lexicographic-sort", "--si"]).ignored_by_format(), Some("--lexicographic-sort"));
+        assert_eq!(usage(&["--format", "svg", "--lexicographic-sort"]).ignored_by_format(), Some("--lexicographic-sort"));
+        assert_eq!(usage(&["--format", "csv", "--si"]).ignored_by_format(), Some("--si"));
+        assert_eq!(usage(&["--format", "json", "--bytes"]).ignored_by_format(), Some("--bytes"));
+        assert_eq!(usage(&["--format", "ncdu", "--block-size", "1K"]).ignored_by_format(), Some("--block-size"));
+        assert_eq!(usage(&["--format", "svg", "--si"]).ignored_by_format(), None);
+        assert_eq!(usage(&["--format", "svg", "--block-size", "1K"]).ignored_by_format(), None);
+        assert_eq!(usage(&["--format", "tree", "--lexicographic-sort", "--bytes"]).ignored_by_format(), None);
+    }
+
+    /// Tests that the scan options are rejected along with a saved tree.
+    #[test]
+    fn test_load_rejects_scan_options() {
+        let parse = |args: &[&str]| Cli::try_parse_from(["du"].iter().chain(args)).map(|_| ());
+        assert!(parse(&["Usage", "--load", "t.json", "--format", "csv"]).is_ok());
+        for option in [
+            &["--path", "."][..],
+            &["--include", "*.rs"],
+            &["--exclude", "a/"],
+            &["--regex"],
+            &["--count-links"],
+            &["--symlinks", "always"],
+            &["--ignore-files"],
+            &["--only-ignored"],
+            &["-x"],
+            &["--threads", "2"],
+        ] {
+            let error = parse(&[&["Usage", "--load", "t.json"][..], option].concat()).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ArgumentConflict, "{option:?}");
+        }
+        for option in [&["-x"][..], &["--threads", "2"]] {
+            let error = parse(&[&["report", "--html", "r.html", "--load", "t.json"][..], option].concat()).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ArgumentConflict, "{option:?}");
+        }
+    }
+}
@@ -0,0 +1,330 @@
+// Export et import au format JSON de ncdu (https://dev.yorhel.nl/ncdu/jsonfmt),
+// pour ouvrir un parcours de cet outil dans ncdu (`ncdu -f`) et inversement.
+//
+//   [1, 2, {"progname": ..., "progver": ..., "timestamp": ...}, <racine>]
+//
+// Un répertoire est un tableau dont le premier élément décrit le répertoire
+// lui-même et les suivants ses entrées ; toute autre entrée est un objet.
+// Chaque description porte "name" (chemin complet pour la racine, nom seul
+// ensuite), "asize" et "dsize", qui sont les tailles propres de l'entrée et
+// non des totaux : ncdu cumule lui-même les tailles des répertoires.
+//
+// Correspondances avec les nœuds de l'arbre :
+//   lien symbolique   "notreg": true ; la cible n'est pas transmise et le lien
+//                     revient comme un fichier
+//   point de montage  "excluded": "otherfs" (ou "kernfs" à l'import)
+//   erreur            "read_error": true
+//   lien physique     un fichier déjà compté ailleurs est écrit avec des
+//                     tailles nulles ; à l'import, les fichiers "hlnkc" de même
+//                     "dev" et "ino" ne sont comptés qu'une fois
+// Les entrées exclues par motif ("pattern", "frmlnk") sont ignorées à l'import.
+//
+// Un export qui descend à plus de MAX_DEPTH répertoires sous la racine est
+// refusé (voir load_json).
+
+use crate::error::{DuError, Result};
+use crate::file_tree::{EntryNode, FileTree, InodeId};
+use crate::json::{from_unix_seconds, to_unix_seconds, MAX_DEPTH};
+use crate::size::Size;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+// Version du format écrite dans chaque export
+pub const NCDU_MAJOR_VERSION: u64 = 1;
+pub const NCDU_MINOR_VERSION: u64 = 2;
+
+// Chemin d'une entrée importée, None si elle est ignorée, ou la raison de l'échec
+type Imported = std::result::Result<Option<PathBuf>, String>;
+
+impl FileTree {
+    // Description ncdu de `path`, sans les entrées d'un répertoire
+    fn ncdu_info(&self, path: &Path, node: &EntryNode) -> Map<String, Value> {
+        let name = if path == self.get_root() {
+            path.to_string_lossy()
+        } else {
+            path.file_name().unwrap_or_default().to_string_lossy()
+        };
+        let mut info = Map::new();
+        info.insert("name".to_string(), json!(name));
+        let (apparent_size, disk_size) = match node {
+            // un répertoire ne porte que ses propres blocs
+            EntryNode::Directory { children, .. } => {
+                let counted = |child: &PathBuf| match self.map.get(child) {
+                    Some(EntryNode::File { counted_elsewhere: true, .. }) | None => Size(0),
+                    Some(child) => child.disk_size(),
+                };
+                (Size(0), node.disk_size().saturating_sub(children.iter().map(counted).sum()))
+            }
+            EntryNode::File { counted_elsewhere: true, .. } => (Size(0), Size(0)),
+            _ => (node.apparent_size(), node.disk_size()),
+        };
+        info.insert("asize".to_string(), json!(apparent_size.0));
+        info.insert("dsize".to_string(), json!(disk_size.0));
+        if let Some(modified) = node.modified() {
+            info.insert("mtime".to_string(), json!(to_unix_seconds(modified)));
+        }
+        match node {
+            EntryNode::Symlink { .. } => {
+                info.insert("notreg".to_string(), json!(true));
+            }
+            EntryNode::MountPoint { device, .. } => {
+                info.insert("dev".to_string(), json!(device));
+                info.insert("excluded".to_string(), json!("otherfs"));
+            }
+            EntryNode::Error { .. } => {
+                info.insert("read_error".to_string(), json!(true));
+            }
+            EntryNode::File { .. } | EntryNode::Directory { .. } => (),
+        }
+        info
+    }
+
+    // Écrit récursivement `path` et, pour un répertoire, ses entrées
+    fn write_ncdu_entry<W: Write>(&self, writer: &mut W, path: &Path) -> io::Result<()> {
+        let Some(node) = self.map.get(path) else {
+            return Ok(());
+        };
+        let info = self.ncdu_info(path, node);
+        match node {
+            EntryNode::Directory { children, .. } => {
+                write!(writer, "[")?;
+                serde_json::to_writer(&mut *writer, &info)?;
+                for child in children {
+                    writeln!(writer, ",")?;
+                    self.write_ncdu_entry(writer, child)?;
+                }
+                write!(writer, "]")
+            }
+            _ => Ok(serde_json::to_writer(&mut *writer, &info)?),
+        }
+    }
+
+    // Écrit l'arbre complet au format JSON de ncdu
+    pub fn write_ncdu<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        let metadata = json!({
+            "progname": env!("CARGO_PKG_NAME"),
+            "progver": env!("CARGO_PKG_VERSION"),
+            "timestamp": to_unix_seconds(SystemTime::now()),
+        });
+        writeln!(writer, "[{NCDU_MAJOR_VERSION},{NCDU_MINOR_VERSION},{metadata},")?;
+        self.write_ncdu_entry(&mut writer, self.get_root())?;
+        writeln!(writer, "]")?;
+        writer.flush()
+    }
+
+    // Construit un arbre à partir d'un export de ncdu déjà lu depuis `file` ;
+    // les tailles des répertoires sont recalculées à partir de leurs entrées
+    pub fn from_ncdu(file: &Path, value: &Value) -> Result<FileTree> {
+        let invalid = |reason: String| DuError::InvalidInput(file.to_path_buf(), reason);
+        let major = value.get(0).and_then(Value::as_u64);
+        if major != Some(NCDU_MAJOR_VERSION) {
+            return Err(invalid(format!(
+                "unsupported ncdu format version {}, expected {}",
+                value.get(0).unwrap_or(&Value::Null),
+                NCDU_MAJOR_VERSION
+            )));
+        }
+        let root = value.get(3).filter(|root| root.is_array()).ok_or_else(|| invalid("missing root directory".to_string()))?;
+
+        let mut map = HashMap::new();
+        let mut shared_inodes = HashMap::new();
+        let root = insert_ncdu_entry(&mut map, &mut shared_inodes, None, 0, 0, root)
+            .map_err(invalid)?
+            .ok_or_else(|| invalid("root directory is excluded".to_string()))?;
+        Ok(FileTree::from_entries(root, map, shared_inodes))
+    }
+}
+
+// Vrai si `name` est le nom d'une entrée de répertoire, sans séparateur
+fn is_entry_name(name: &str) -> bool {
+    !name.contains('/') && matches!(Path::new(name).components().collect::<Vec<_>>()[..], [Component::Normal(_)])
+}
+
+// Ajoute l'entrée ncdu `entry`, située `depth` répertoires sous la racine, et
+// ses descendants à la map des nœuds. Les répertoires n'y portent que leurs
+// propres blocs, comme après le parcours.
+fn insert_ncdu_entry(
+    map: &mut HashMap<PathBuf, EntryNode>,
+    shared_inodes: &mut HashMap<PathBuf, InodeId>,
+    parent: Option<&Path>,
+    parent_device: u64,
+    depth: usize,
+    entry: &Value,
+) -> Imported {
+    let (info, entries) = match entry {
+        Value::Array(items) => (items.first(), Some(&items[1.min(items.len())..])),
+        _ => (Some(entry), None),
+    };
+    let info = info.and_then(Value::as_object).ok_or("entry without description")?;
+    let name = info.get("name").and_then(Value::as_str).ok_or("entry without name")?;
+    // sous la racine, un nom désigne une seule entrée du répertoire parent :
+    // ".", ".." ou un chemin feraient sortir du répertoire ou boucler sur lui
+    let path = match parent {
+        None if name.is_empty() => return Err("root entry without name".to_string()),
+        None => PathBuf::from(name),
+        Some(parent) if is_entry_name(name) => parent.join(name),
+        Some(parent) => return Err(format!("invalid entry name '{}' in '{}'", name, parent.display())),
+    };
+    if depth > MAX_DEPTH {
+        return Err(format!("'{}' is nested deeper than {MAX_DEPTH} directories", path.display()));
+    }
+    if map.contains_key(&path) {
+        return Err(format!("duplicate entry '{}'", path.display()));
+    }
+    let number = |field: &str| info.get(field).and_then(Value::as_u64);
+    let flag = |field: &str| info.get(field).and_then(Value::as_bool).unwrap_or(false);
+    // un champ "dev" absent signifie le même périphérique que le parent
+    let device = number("dev").unwrap_or(parent_device);
+    let apparent_size = Size(number("asize").unwrap_or(0));
+    let disk_size = Size(number("dsize").unwrap_or(0));
+    let modified = info.get("mtime").and_then(Value::as_i64).map(from_unix_seconds);
+
+    let node = match (info.get("excluded").and_then(Value::as_str), entries) {
+        (Some("pattern" | "frmlnk"), _) => return Ok(None),
+        (Some(excluded @ ("otherfs" | "othfs" | "kernfs")), _) => EntryNode::MountPoint {
+            device,
+            filesystem: (excluded == "kernfs").then(|| excluded.to_string()),
+        },
+        (Some(other), _) => return Err(format!("unknown exclusion '{other}' for '{}'", path.display())),
+        (None, Some([])) | (None, None) if flag("read_error") => {
+            EntryNode::Error { message: "read error reported by ncdu".to_string() }
+        }
+        (None, Some(entries)) => {
+            let mut children = vec![];
+            for entry in entries {
+                children.extend(insert_ncdu_entry(map, shared_inodes, Some(&path), device, depth + 1, entry)?);
+            }
+            // même ordre que pour un parcours
+            children.sort();
+            EntryNode::Directory { apparent_size: Size(0), disk_size, children, modified }
+        }
+        (None, None) => {
+            if let (true, Some(inode)) = (flag("hlnkc"), number("ino")) {
+                shared_inodes.insert(path.clone(), (device, inode));
+            }
+            EntryNode::File { apparent_size, disk_size, counted_elsewhere: false, modified }
+        }
+    };
+    map.insert(path.clone(), node);
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_tree::tests::{sample_tree, with_main_stack};
+    use std::fs;
+
+    /// Tests that directories are written as arrays led by their own entry.
+    #[test]
+    fn test_ncdu_export_layout() {
+        let (temp_dir, file_tree) = sample_tree();
+        let root = temp_dir.path();
+
+        let mut out = vec![];
+        file_tree.write_ncdu(&mut out).unwrap();
+        let value: Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(value[0], 1);
+        assert_eq!(value[1], 2);
+        assert_eq!(value[2]["progname"], "du");
+        let tree = &value[3];
+        assert_eq!(tree[0]["name"], root.to_string_lossy().as_ref());
+        // Directories carry their own size, not the total
+        assert_eq!(tree[0]["asize"], 0);
+        assert_eq!(tree[1][0]["name"], "big");
+        assert_eq!(tree[1][1][0]["name"], "deep");
+        assert_eq!(tree[1][1][1]["name"], "leaf.txt");
+        assert_eq!(tree[1][1][1]["asize"], 300);
+        assert_eq!(tree[1][2]["name"], "mid.txt");
+        assert_eq!(tree[2]["name"], "small.txt");
+        assert_eq!(tree[2]["asize"], 100);
+
+        // Reloading gives back the same totals
+        let file = root.join("export.json");
+        fs::write(&file, &out).unwrap();
+        let loaded = FileTree::load_json(&file).unwrap();
+        assert_eq!(loaded.get_root(), root);
+        assert_eq!(loaded.get_size(root), Some(Size(600)));
+        assert_eq!(loaded.map[root].disk_size(), file_tree.map[root].disk_size());
+        assert_eq!(loaded.get_size(&root.join("big")), Some(Size(500)));
+    }
+
+    /// Tests that hard links, exclusions and read errors of a dump are kept.
+    #[test]
+    fn test_ncdu_import() {
+        let dump = json!([1, 2, {"progname": "ncdu", "progver": "1.19", "timestamp": 1700000000},
+            [{"name": "/srv", "asize": 4096, "dsize": 4096, "dev": 7},
+                {"name": "b.bin", "asize": 1000, "dsize": 4096, "hlnkc": true, "ino": 12, "nlink": 2},
+                [{"name": "data", "asize": 4096, "dsize": 4096, "mtime": 1600000000},
+                    {"name": "a.bin", "asize": 1000, "dsize": 4096, "hlnkc": true, "ino": 12, "nlink": 2},
+                    {"name": "link", "asize": 10, "dsize": 0, "notreg": true}],
+                {"name": "cache", "excluded": "pattern"},
+                {"name": "proc", "excluded": "kernfs"},
+                {"name": "secret", "read_error": true}]]);
+        let file_tree = FileTree::from_ncdu(Path::new("dump.json"), &dump).unwrap();
+        let root = Path::new("/srv");
+
+        // The two names of inode 12 count once, under the first path in sorted order
+        assert!(matches!(
+            file_tree.map.get(&root.join("data/a.bin")),
+            Some(EntryNode::File { counted_elsewhere: true, .. })
+        ));
+        assert_eq!(file_tree.get_size(&root.join("data")), Some(Size(10)));
+        assert_eq!(file_tree.get_size(root), Some(Size(1010)));
+        assert_eq!(file_tree.map[root].disk_size(), Size(4096 * 3));
+        assert!(file_tree.map.get(&root.join("data")).unwrap().modified().is_some());
+        assert!(!file_tree.map.contains_key(&root.join("cache")));
+        assert!(matches!(file_tree.map.get(&root.join("proc")), Some(EntryNode::MountPoint { device: 7, .. })));
+        assert!(matches!(file_tree.map.get(&root.join("secret")), Some(EntryNode::Error { .. })));
+    }
+
+    /// Tests that entry names which are not a single component, and
+    /// duplicate entries, are rejected instead of looping.
+    #[test]
+    fn test_ncdu_import_rejects_invalid_names() {
+        for name in ["", ".", "..", "a/b", "/abs"] {
+            let dump = json!([1, 2, {}, [{"name": "/srv"}, [{"name": "x"}, {"name": name, "asize": 5}]]]);
+            let error = FileTree::from_ncdu(Path::new("dump.json"), &dump).err().unwrap();
+            assert!(matches!(error, DuError::InvalidInput(..)), "name {name:?}");
+        }
+        let dump = json!([1, 2, {}, [{"name": "/srv"}, {"name": "a"}, {"name": "a"}]]);
+        let error = FileTree::from_ncdu(Path::new("dump.json"), &dump).err().unwrap();
+        assert!(error.to_string().contains("duplicate entry '/srv/a'"));
+    }
+
+    /// Tests that dumps of another major version are rejected.
+    #[test]
+    fn test_ncdu_import_rejects_other_versions() {
+        let dump = json!([2, 0, {}, [{"name": "/"}]]);
+        let error = FileTree::from_ncdu(Path::new("dump.json"), &dump).err().unwrap();
+        assert!(matches!(error, DuError::InvalidInput(..)));
+    }
+
+    /// Tests that a dump nested deeper than MAX_DEPTH is an input error.
+    #[test]
+    fn test_ncdu_import_depth_limit() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("dump.json");
+        // Written as text: a Value this deep could not be built or dropped
+        let dump = |depth: usize| {
+            let entries = r#"[{"name": "d"},"#.repeat(depth) + r#"{"name": "f", "asize": 1}"# + &"]".repeat(depth);
+            fs::write(&file, format!(r#"[1, 2, {{}}, [{{"name": "/srv"}}, {entries}]]"#)).unwrap();
+            FileTree::load_json(&file)
+        };
+        with_main_stack(|| {
+            let file_tree = dump(MAX_DEPTH - 1).unwrap();
+            assert_eq!(file_tree.get_size(Path::new("/srv")), Some(Size(1)));
+            assert_eq!(file_tree.map.len(), MAX_DEPTH + 1);
+            for depth in [MAX_DEPTH, 30_000] {
+                let error = dump(depth).err().unwrap();
+                assert!(matches!(&error, DuError::InvalidInput(..)));
+                assert!(error.to_string().contains("deeper than 1024 directories"), "{error}");
+            }
+        });
+    }
+}
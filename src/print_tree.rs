@@ -1,75 +1,292 @@
-use crate::file_tree::{FileTree, EntryNode};
-use std::path::{Path, PathBuf};
+use crate::file_tree::{EntryFilter, FileTree, EntryNode, MatchingSizes};
+use crate::size::{Size, SizeFormat};
+use std::fmt::Write;
+use std::path::Path;
+
+// Options d'affichage de l'arbre
+#[derive(Default)]
+pub struct ShowOptions<'a> {
+    // trie par ordre lexicographique plutôt que par taille décroissante
+    pub lexicographic_sort: bool,
+    // n'affiche que les fichiers qui passent ce filtre et les répertoires qui en
+    // contiennent, même indirectement
+    pub filter: EntryFilter<'a>,
+    // les entrées plus petites sont regroupées en une seule ligne par répertoire
+    pub min_size: Option<Size>,
+    // écriture des tailles (unités IEC ou SI, octets, blocs)
+    pub size_format: SizeFormat,
+    // profondeur maximale affichée (la racine est à la profondeur 0)
+    pub max_depth: Option<usize>,
+}
+
+// Numéro de périphérique au format majeur:mineur (encodage de glibc)
+#[cfg(target_os = "linux")]
+fn format_device(device: u64) -> String {
+    let major = ((device >> 8) & 0xfff) | ((device >> 32) & !0xfff);
+    let minor = (device & 0xff) | ((device >> 12) & !0xff);
+    format!("{}:{}", major, minor)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn format_device(device: u64) -> String {
+    device.to_string()
+}
 
 impl FileTree {
     // Fonction publique pour afficher l'arbre
-    pub fn show(&self, lexicographic_sort: bool, file_extension: Option<&str>) {
+    pub fn show(&self, options: &ShowOptions) {
+        print!("{}", self.render(options));
+    }
+
+    // Construit le texte de l'arbre, une ligne par nœud
+    pub fn render(&self, options: &ShowOptions) -> String {
+        let mut out = String::new();
         let root = self.get_root();  // Obtient la racine de l'arbre
-        self.display_tree(&root, 0, lexicographic_sort, file_extension);  // Appelle la fonction récursive pour afficher l'arbre
+        let truncated = options.max_depth == Some(0);
+        // les tailles filtrées sont calculées une seule fois pour tout l'arbre
+        let matching = options.filter.is_active().then(|| self.matching_sizes(&options.filter));
+        let matching = matching.as_ref();
+        writeln!(out, "{}", self.entry_label(root, &root.to_string_lossy(), truncated, options, matching)).unwrap();
+        if !truncated {
+            self.display_tree(&mut out, root, "", 0, options, matching);  // Appelle la fonction récursive pour afficher l'arbre
+        }
+        out
     }
 
-    // Fonction récursive pour afficher l'arbre
+    // Texte affiché pour un nœud : son nom, sa taille, et le nombre d'entrées
+    // masquées lorsque l'affichage de ce répertoire est tronqué
+    fn entry_label(
+        &self,
+        path: &Path,
+        name: &str,
+        truncated: bool,
+        options: &ShowOptions,
+        matching: Option<&MatchingSizes>,
+    ) -> String {
+        let format = |size: Size| options.size_format.format(size);
+        let size = self.get_size(path).map_or_else(|| "N/A".to_string(), format);
+        // les répertoires se terminent par '/', sauf s'ils l'ont déjà (racine "/")
+        let slash = if name.ends_with('/') { "" } else { "/" };
+        match self.map.get(path) {
+            Some(EntryNode::Directory { .. }) => {
+                // avec un filtre, la taille du contenu correspondant précède la taille totale
+                let size = if matching.is_some() {
+                    format!("{} of {}", format(self.shown_size(path, matching)), size)
+                } else {
+                    size
+                };
+                // les enfants ne sont comptés que pour un répertoire dont le contenu est masqué
+                let hidden = if truncated {
+                    self.get_children(path, false, matching).map_or(0, |children| children.len())
+                } else {
+                    0
+                };
+                if hidden > 0 {
+                    format!("{}{} ({}) [{} hidden]", name, slash, size, count_of(hidden, "entry", "entries"))
+                } else {
+                    format!("{}{} ({})", name, slash, size)
+                }
+            }
+            Some(EntryNode::File { counted_elsewhere: true, .. }) => {
+                format!("{} ({}) [counted elsewhere]", name, size)
+            }
+            Some(EntryNode::Symlink { target, is_loop, .. }) => format!(
+                "{} -> {} ({}){}",
+                name,
+                target.to_string_lossy(),
+                size,
+                if *is_loop { " [loop]" } else { "" }
+            ),
+            Some(EntryNode::Error { message }) => format!("{} [{}]", name, message),
+            Some(EntryNode::MountPoint { device, filesystem }) => format!(
+                "{}/ [mount point not crossed: {}device {}]",
+                name,
+                filesystem.as_ref().map_or_else(String::new, |fs| format!("{}, ", fs)),
+                format_device(*device)
+            ),
+            // signale les fichiers creux, dont les deux tailles divergent fortement
+            Some(node) if node.is_sparse() => format!(
+                "{} ({}) [sparse: {} apparent, {} on disk]",
+                name,
+                size,
+                format(node.apparent_size()),
+                format(node.disk_size())
+            ),
+            _ => format!("{} ({})", name, size),
+        }
+    }
+
+    // Taille qui sert à comparer une entrée au seuil min_size : celle du contenu
+    // qui passe le filtre s'il y en a un
+    fn shown_size(&self, path: &Path, matching: Option<&MatchingSizes>) -> Size {
+        match matching {
+            Some(matching) => matching.get(path).copied().unwrap_or_default(),
+            None => self.get_size(path).unwrap_or_default(),
+        }
+    }
+
+    // Fonction récursive pour afficher l'arbre : `prefix` contient les traits
+    // verticaux des niveaux supérieurs, `depth` la profondeur de `current_path`
     fn display_tree(
         &self,
+        out: &mut String,
         current_path: &Path,
+        prefix: &str,
         depth: usize,
-        lexicographic_sort: bool,
-        file_extension: Option<&str>,
+        options: &ShowOptions,
+        matching: Option<&MatchingSizes>,
     ) {
         // Vérifie s'il y a des enfants pour le chemin actuel
-        if let Some(children) = self.get_children(current_path, lexicographic_sort, file_extension) {
-            // Obtient la taille du répertoire actuel
-            let size = self.get_size(&current_path);
-            // Calcule l'indentation en fonction de la profondeur
-            let indentation = "  ".repeat(depth);
-            // Affiche le répertoire actuel avec sa taille
-            println!(
-                "{} Directory: {} ({})",
-                indentation,
-                current_path.to_string_lossy(),
-                size.map_or_else(|| "N/A".to_string(), |s| s.to_string())
-            );
-
-            // Parcourt les enfants et affiche leurs informations
-            for child_path in children.iter() {
-                if child_path.is_dir() {
-                    // Si l'enfant est un répertoire, affiche ses informations
-                    let size = self.get_size(&child_path);
-                    let indentation = "  ".repeat(depth + 1);
-                    println!(
-                        "{} Directory: {} ({})",
-                        indentation,
-                        child_path.to_string_lossy(),
-                        size.map_or_else(|| "N/A".to_string(), |s| s.to_string())
-                    );
-
-                    // Récupère les fichiers du sous-répertoire et les affiche
-                    let sub_files = self.files(child_path.to_path_buf());
-                    for file in sub_files {
-                        let indentation = "  ".repeat(depth + 2);
-                        let file_size = self.get_size(&file);
-                        println!(
-                            "{} File: {} ({})",
-                            indentation,
-                            file.to_string_lossy(),
-                            file_size.map_or_else(|| "N/A".to_string(), |s| s.to_string())
-                        );
-                    }
-                } else if child_path.is_file() {
-                    // Si l'enfant est un fichier, affiche ses informations
-                    let indentation = "  ".repeat(depth + 1);
-                    let child_size = self.get_size(&child_path);
-                    println!(
-                        "{} File: {} ({})",
-                        indentation,
-                        child_path.to_string_lossy(),
-                        child_size.map_or_else(|| "N/A".to_string(), |s| s.to_string())
-                    );
-                } else {
-                    // Cas où l'enfant n'est ni un répertoire ni un fichier
-                    println!(" ");
+        let Some(mut children) =
+            self.get_children(current_path, options.lexicographic_sort, matching)
+        else {
+            return;
+        };
+
+        // les entrées sous le seuil sont regroupées sur une dernière ligne
+        let (mut smaller_items, mut smaller_size) = (0, Size(0));
+        if let Some(min_size) = options.min_size {
+            children.retain(|child| {
+                let size = self.shown_size(child, matching);
+                if size < min_size {
+                    smaller_items += 1;
+                    smaller_size += size;
                 }
+                size >= min_size
+            });
+        }
+
+        // Les enfants sont à depth + 1 ; leurs propres enfants ne sont affichés
+        // que si depth + 2 ne dépasse pas la profondeur maximale
+        let truncated = options.max_depth.is_some_and(|max_depth| depth + 2 > max_depth);
+
+        for (index, child_path) in children.iter().enumerate() {
+            let last = index + 1 == children.len() && smaller_items == 0;
+            let connector = if last { "└── " } else { "├── " };
+            let name = child_path
+                .file_name()
+                .map_or_else(|| child_path.to_string_lossy(), |name| name.to_string_lossy());
+            writeln!(out, "{}{}{}", prefix, connector, self.entry_label(child_path, &name, truncated, options, matching)).unwrap();
+
+            // Si l'enfant est un répertoire, affiche son contenu
+            if !truncated && matches!(self.map.get(child_path), Some(EntryNode::Directory { .. })) {
+                let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                self.display_tree(out, child_path, &child_prefix, depth + 1, options, matching);
             }
         }
+        if smaller_items > 0 {
+            let smaller_size = options.size_format.format(smaller_size);
+            let smaller_items = count_of(smaller_items, "smaller item", "smaller items");
+            writeln!(out, "{}└── <{}> ({})", prefix, smaller_items, smaller_size).unwrap();
+        }
+    }
+}
+
+// `count` suivi du nom au singulier ou au pluriel, ex. "1 entry", "2 entries"
+pub(crate) fn count_of(count: usize, singular: &str, plural: &str) -> String {
+    format!("{} {}", count, if count == 1 { singular } else { plural })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_tree::tests::sample_tree;
+    use std::fs;
+
+    /// Tests that every level of the tree is drawn with its connectors.
+    #[test]
+    fn test_render_recurses_to_any_depth() {
+        let (temp_dir, file_tree) = sample_tree();
+        let rendered = file_tree.render(&ShowOptions::default());
+        let lines: Vec<&str> = rendered.lines().skip(1).collect();
+
+        assert!(rendered.starts_with(&*temp_dir.path().to_string_lossy()));
+        assert_eq!(
+            lines,
+            vec![
+                "├── big/ (500 B)",
+                "│   ├── deep/ (300 B)",
+                "│   │   └── leaf.txt (300 B)",
+                "│   └── mid.txt (200 B)",
+                "└── small.txt (100 B)",
+            ]
+        );
+    }
+
+    /// Tests that entries below --max-depth are counted but not drawn.
+    #[test]
+    fn test_render_max_depth_keeps_truncated_sizes() {
+        let (_temp_dir, file_tree) = sample_tree();
+        let options = ShowOptions { max_depth: Some(1), ..Default::default() };
+        let rendered = file_tree.render(&options);
+        let lines: Vec<&str> = rendered.lines().skip(1).collect();
+
+        assert_eq!(
+            lines,
+            vec!["├── big/ (500 B) [2 entries hidden]", "└── small.txt (100 B)"]
+        );
+
+        // A single hidden entry is counted in the singular
+        let options = ShowOptions { max_depth: Some(2), ..Default::default() };
+        let rendered = file_tree.render(&options);
+        assert!(rendered.contains("│   ├── deep/ (300 B) [1 entry hidden]\n"));
+    }
+
+    /// Tests that a filtered directory shows its matching size before its total size.
+    #[test]
+    fn test_render_extension_filter_shows_matching_and_full_sizes() {
+        let (temp_dir, _) = sample_tree();
+        fs::write(temp_dir.path().join("big/deep/skip.dat"), vec![0; 50]).unwrap();
+        fs::create_dir(temp_dir.path().join("empty")).unwrap();
+        let file_tree = FileTree::new(temp_dir.path()).unwrap();
+        let filter = EntryFilter { file_extension: Some(".txt"), ..Default::default() };
+        let options = ShowOptions { filter, ..Default::default() };
+        let rendered = file_tree.render(&options);
+        let lines: Vec<&str> = rendered.lines().skip(1).collect();
+
+        assert!(rendered.lines().next().unwrap().ends_with("(600 B of 650 B)"));
+        assert_eq!(
+            lines,
+            vec![
+                "├── big/ (500 B of 550 B)",
+                "│   ├── deep/ (300 B of 350 B)",
+                "│   │   └── leaf.txt (300 B)",
+                "│   └── mid.txt (200 B)",
+                "└── small.txt (100 B)",
+            ]
+        );
+    }
+
+    /// Tests that sizes can be written in bytes, without a unit.
+    #[test]
+    fn test_render_raw_bytes() {
+        let (_temp_dir, file_tree) = sample_tree();
+        let options = ShowOptions { size_format: SizeFormat::bytes(), max_depth: Some(1), ..Default::default() };
+        let rendered = file_tree.render(&options);
+        let lines: Vec<&str> = rendered.lines().skip(1).collect();
+
+        assert_eq!(lines, vec!["├── big/ (500) [2 entries hidden]", "└── small.txt (100)"]);
+    }
+
+    /// Tests that entries under --min-size are grouped on a last line.
+    #[test]
+    fn test_render_collapses_smaller_items() {
+        let (temp_dir, _) = sample_tree();
+        fs::write(temp_dir.path().join("tiny.txt"), vec![0; 10]).unwrap();
+        let file_tree = FileTree::new(temp_dir.path()).unwrap();
+        let options = ShowOptions { min_size: Some(Size(250)), ..Default::default() };
+        let rendered = file_tree.render(&options);
+        let lines: Vec<&str> = rendered.lines().skip(1).collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "├── big/ (500 B)",
+                "│   ├── deep/ (300 B)",
+                "│   │   └── leaf.txt (300 B)",
+                "│   └── <1 smaller item> (200 B)",
+                "└── <2 smaller items> (110 B)",
+            ]
+        );
     }
 }
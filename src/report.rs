@@ -0,0 +1,246 @@
+// Rapport HTML autonome : une carte proportionnelle (treemap) interactive de
+// l'arbre, dans un seul fichier lisible hors ligne. Le modèle de l'arbre est
+// inclus en JSON dans la page (voir report.html), qui le dessine et gère le
+// zoom et les infobulles. Les tailles y sont déjà écrites selon le format
+// demandé, et chaque fichier porte la catégorie de son extension.
+
+use crate::file_tree::{EntryNode, FileTree};
+use crate::print_tree::count_of;
+use crate::size::{Size, SizeFormat};
+use serde::Serialize;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Page dans laquelle le modèle remplace {{DATA}}
+const TEMPLATE: &str = include_str!("report.html");
+
+// Fraction de la taille totale en dessous de laquelle les entrées d'un
+// répertoire sont regroupées : une entrée de 1/100 000 du total occupe environ
+// 20 pixels sur un écran de 2 mégapixels, et garde quelques pixels après un zoom
+pub const MIN_SHARE: u64 = 100_000;
+
+// Famille d'extensions, avec la couleur qui la représente
+pub struct Category {
+    pub name: &'static str,
+    pub color: &'static str,
+    extensions: &'static [&'static str],
+}
+
+// La dernière catégorie reçoit tout ce qui n'est pas reconnu
+pub const CATEGORIES: [Category; 8] = [
+    Category {
+        name: "images",
+        color: "#f28e2b",
+        extensions: &["png", "jpg", "jpeg", "gif", "bmp", "svg", "webp", "tif", "tiff", "ico", "heic", "psd", "raw"],
+    },
+    Category {
+        name: "video",
+        color: "#e15759",
+        extensions: &["mp4", "mkv", "avi", "mov", "webm", "wmv", "flv", "m4v", "mpg", "mpeg"],
+    },
+    Category {
+        name: "audio",
+        color: "#b07aa1",
+        extensions: &["mp3", "flac", "wav", "ogg", "opus", "m4a", "aac", "wma"],
+    },
+    Category {
+        name: "archives",
+        color: "#edc948",
+        extensions: &["zip", "tar", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "deb", "rpm", "iso", "img", "dmg"],
+    },
+    Category {
+        name: "documents",
+        color: "#4e79a7",
+        extensions: &[
+            "pdf", "doc", "docx", "odt", "xls", "xlsx", "ods", "ppt", "pptx", "odp", "txt", "md", "rtf", "csv", "epub",
+            "tex",
+        ],
+    },
+    Category {
+        name: "code",
+        color: "#59a14f",
+        extensions: &[
+            "rs", "c", "h", "cc", "cpp", "hpp", "py", "js", "ts", "jsx", "tsx", "java", "go", "rb", "php", "sh", "pl",
+            "lua", "swift", "kt", "scala", "cs", "html", "css", "scss", "json", "toml", "yaml", "yml", "xml", "sql",
+        ],
+    },
+    Category {
+        name: "binaries",
+        color: "#76b7b2",
+        extensions: &["so", "a", "o", "dll", "exe", "dylib", "bin", "class", "jar", "wasm", "rlib", "rmeta", "pyc"],
+    },
+    Category { name: "other", color: "#bab0ac", extensions: &[] },
+];
+
+// Indice dans CATEGORIES de la catégorie d'un fichier, d'après son extension
+pub fn category_of(path: &Path) -> usize {
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+    CATEGORIES
+        .iter()
+        .position(|category| extension.as_deref().is_some_and(|extension| category.extensions.contains(&extension)))
+        .unwrap_or(CATEGORIES.len() - 1)
+}
+
+#[derive(Serialize)]
+struct ReportCategory {
+    name: &'static str,
+    color: &'static str,
+}
+
+#[derive(Serialize)]
+struct ReportNode {
+    name: String,
+    size: u64,
+    // taille écrite selon le format demandé
+    label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<usize>,
+    // enfants triés par taille décroissante, pour un répertoire
+    #[serde(skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<ReportNode>>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    categories: Vec<ReportCategory>,
+    tree: ReportNode,
+}
+
+impl FileTree {
+    // Construit récursivement le modèle de `path` ; les entrées vides sont
+    // omises et celles de moins de `threshold` regroupées en une seule
+    fn report_node(&self, path: &Path, name: String, threshold: Size, size_format: &SizeFormat) -> ReportNode {
+        let size = self.get_size(path).unwrap_or_default();
+        let mut node = ReportNode {
+            name,
+            size: size.0,
+            label: size_format.format(size),
+            category: None,
+            children: None,
+        };
+        match self.map.get(path) {
+            Some(EntryNode::Directory { children, .. }) => {
+                let mut kept = vec![];
+                let (mut smaller_items, mut smaller_size) = (0, Size(0));
+                for child in children {
+                    let child_size = self.counted_size(child).unwrap_or_default();
+                    if child_size == Size(0) {
+                        continue;
+                    }
+                    if child_size < threshold {
+                        smaller_items += 1;
+                        smaller_size += child_size;
+                    } else {
+                        let name = child.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                        kept.push(self.report_node(child, name, threshold, size_format));
+                    }
+                }
+                if smaller_items > 0 {
+                    kept.push(ReportNode {
+                        name: format!("<{}>", count_of(smaller_items, "smaller item", "smaller items")),
+                        size: smaller_size.0,
+                        label: size_format.format(smaller_size),
+                        category: Some(CATEGORIES.len() - 1),
+                        children: None,
+                    });
+                }
+                kept.sort_by_key(|child| std::cmp::Reverse(child.size));
+                node.children = Some(kept);
+            }
+            _ => node.category = Some(category_of(path)),
+        }
+        node
+    }
+
+    // Écrit le rapport HTML autonome de l'arbre
+    pub fn write_html_report<W: Write>(&self, writer: W, size_format: &SizeFormat) -> io::Result<()> {
+        let root = self.get_root();
+        let total = self.get_size(root).unwrap_or_default();
+        let threshold = Size(total.0.div_ceil(MIN_SHARE));
+        let report = Report {
+            categories: CATEGORIES
+                .iter()
+                .map(|category| ReportCategory { name: category.name, color: category.color })
+                .collect(),
+            tree: self.report_node(root, root.to_string_lossy().into_owned(), threshold, size_format),
+        };
+        // "</" fermerait la balise <script> qui contient le modèle, et "<!--"
+        // changerait la façon dont elle est lue : ces caractères, qui ne
+        // peuvent figurer que dans des chaînes, y sont écrits en \uXXXX
+        let data = serde_json::to_string(&report)?
+            .replace('&', "\\u0026")
+            .replace('<', "\\u003c")
+            .replace('>', "\\u003e");
+        let (before, after) = TEMPLATE.split_once("{{DATA}}").expect("the template has a data placeholder");
+
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(before.as_bytes())?;
+        writer.write_all(data.as_bytes())?;
+        writer.write_all(after.as_bytes())?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    // Model embedded in the page's data script
+    fn embedded_model(html: &str) -> &str {
+        let start = html.find("application/json\">").unwrap() + "application/json\">".len();
+        let end = start + html[start..].find("</script>").unwrap();
+        &html[start..end]
+    }
+
+    #[test]
+    fn test_categories() {
+        assert_eq!(CATEGORIES[category_of(Path::new("photo.JPG"))].name, "images");
+        assert_eq!(CATEGORIES[category_of(Path::new("src/main.rs"))].name, "code");
+        assert_eq!(CATEGORIES[category_of(Path::new("backup.tar.gz"))].name, "archives");
+        assert_eq!(CATEGORIES[category_of(Path::new("Makefile"))].name, "other");
+    }
+
+    #[test]
+    fn test_html_report() {
+        // The root path contains "</script>", a file name "<!--<script>"
+        let temp_dir = tempdir().unwrap();
+        let root = &temp_dir.path().join("x<").join("script>");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/a.pdf"), vec![0; 300_000]).unwrap();
+        fs::write(root.join("docs/<!--<script> & co.pdf"), vec![0; 100_000]).unwrap();
+        fs::write(root.join("big.txt"), vec![0; 200_000]).unwrap();
+        fs::write(root.join("tiny.rs"), vec![0; 1]).unwrap();
+        fs::write(root.join("empty"), b"").unwrap();
+        // A second name for a.pdf adds nothing and gets no tile
+        #[cfg(unix)]
+        fs::hard_link(root.join("docs/a.pdf"), root.join("docs/z.pdf")).unwrap();
+        let file_tree = FileTree::new(root).unwrap();
+
+        let mut out = vec![];
+        file_tree.write_html_report(&mut out, &SizeFormat::default()).unwrap();
+        let html = String::from_utf8(out).unwrap();
+
+        // The model can be read back from the page, and no name closes the
+        // script early or opens a comment in it
+        assert_eq!(html.matches("</script>").count(), 2);
+        let model = embedded_model(&html);
+        assert!(!model.contains(['<', '>', '&']));
+        let report: serde_json::Value = serde_json::from_str(model).unwrap();
+
+        let tree = &report["tree"];
+        assert_eq!(tree["name"], root.to_string_lossy().as_ref());
+        assert_eq!(tree["size"], 600_001);
+        let children = tree["children"].as_array().unwrap();
+        // Sorted by decreasing size; the empty file is left out and the
+        // one-byte file falls under the threshold
+        let names: Vec<&str> = children.iter().map(|child| child["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["docs", "big.txt", "<1 smaller item>"]);
+        assert_eq!(children[0]["label"], "390.6 KiB");
+        assert_eq!(children[0]["children"][0]["category"], 4);
+        assert_eq!(children[0]["children"][1]["name"], "<!--<script> & co.pdf");
+        assert_eq!(children[0]["children"].as_array().unwrap().len(), 2);
+        assert_eq!(report["categories"].as_array().unwrap().len(), CATEGORIES.len());
+    }
+}
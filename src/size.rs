@@ -1,18 +1,39 @@
-use std::fmt;
 use std::error::Error;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Sub, SubAssign};
+use std::str::FromStr;
 
 /// Represents a size in bytes.
-#[derive(PartialEq, PartialOrd, Eq, Ord, Copy, Clone, Debug)]
+#[derive(PartialEq, PartialOrd, Eq, Ord, Hash, Copy, Clone, Debug, Default)]
 pub struct Size(pub u64);
 
-/// Custom error type for size conversion problems.
-#[derive(Debug, Clone)]
-pub struct SizeError;
+/// Error returned when a human-readable size cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SizeError {
+    /// The text was empty.
+    Empty,
+    /// The numeric part is missing or is not a non-negative number.
+    InvalidNumber(String),
+    /// The suffix is not a known unit.
+    UnknownUnit(String),
+    /// The value does not fit in 64 bits.
+    Overflow(String),
+}
 
 /// Implements Display for user-friendly formatting of SizeError.
 impl fmt::Display for SizeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Invalid size conversion")
+        match self {
+            SizeError::Empty => write!(f, "empty size"),
+            SizeError::InvalidNumber(text) => write!(f, "'{}' does not start with a number", text),
+            SizeError::UnknownUnit(unit) => write!(
+                f,
+                "unknown unit '{}', expected B, K/KB/KiB, M/MB/MiB, G/GB/GiB, T/TB/TiB, P/PB/PiB or E/EB/EiB",
+                unit
+            ),
+            SizeError::Overflow(text) => write!(f, "'{}' is too large", text),
+        }
     }
 }
 
@@ -21,78 +42,262 @@ impl Error for SizeError {}
 
 /// Implements functionality for the Size structure.
 impl Size {
-    /// Constructs a new Size instance from the number of bytes (with error handling).
-    pub fn new(bytes: u64) -> Result<Self, SizeError> {
-        if bytes <= u64::MAX {
-            Ok(Size(bytes))
+    /// Constructs a new Size instance from the number of bytes.
+    pub fn new(bytes: u64) -> Self {
+        Size(bytes)
+    }
+
+    /// Adds two sizes, returning None on overflow.
+    pub fn checked_add(self, other: Size) -> Option<Size> {
+        self.0.checked_add(other.0).map(Size)
+    }
+
+    /// Subtracts `other`, returning None if it is larger than `self`.
+    pub fn checked_sub(self, other: Size) -> Option<Size> {
+        self.0.checked_sub(other.0).map(Size)
+    }
+
+    /// Multiplies the size by `factor`, returning None on overflow.
+    pub fn checked_mul(self, factor: u64) -> Option<Size> {
+        self.0.checked_mul(factor).map(Size)
+    }
+
+    /// Adds two sizes, stopping at the largest representable size.
+    pub fn saturating_add(self, other: Size) -> Size {
+        self.checked_add(other).unwrap_or(Size(u64::MAX))
+    }
+
+    /// Subtracts `other`, stopping at zero.
+    pub fn saturating_sub(self, other: Size) -> Size {
+        self.checked_sub(other).unwrap_or(Size(0))
+    }
+
+    /// Multiplies the size by `factor`, stopping at the largest representable size.
+    pub fn saturating_mul(self, factor: u64) -> Size {
+        self.checked_mul(factor).unwrap_or(Size(u64::MAX))
+    }
+
+    /// Fraction of `total` that this size represents; 0 when `total` is empty.
+    pub fn ratio(self, total: Size) -> f64 {
+        if total.0 == 0 {
+            0.0
         } else {
-            Err(SizeError)
+            self.0 as f64 / total.0 as f64
         }
     }
+
+    /// Percentage of `total` that this size represents; 0 when `total` is empty.
+    pub fn percent_of(self, total: Size) -> f64 {
+        self.ratio(total) * 100.0
+    }
 }
 
-/// Implements Display for user-friendly formatting of the Size structure.
-impl fmt::Display for Size {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut unit = "B";
-        let mut size = self.0 as f64;
+/// Base of the units used to format a size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnitBase {
+    /// Powers of 1024: KiB, MiB, GiB...
+    #[default]
+    Iec,
+    /// Powers of 1000: kB, MB, GB...
+    Si,
+}
 
-        let kb  = 1024.0 ;
-        let mb = 1024.0 * kb ;
-        let gb = 1024.0 * mb ;
+const IEC_UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+const SI_UNITS: [&str; 7] = ["B", "kB", "MB", "GB", "TB", "PB", "EB"];
 
-        if size < 1024.0 {
-            unit = unit;
-            size = size;
-        }
-        else if kb < size && size < mb {
-            unit = "KB";
-            size = size/kb;
+/// How a size is written: a number followed by a unit, or a plain number of
+/// fixed-size blocks that scripts can parse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeFormat {
+    /// Base of the units picked for human-readable output.
+    pub base: UnitBase,
+    /// Number of decimals printed after a unit larger than a byte.
+    pub precision: usize,
+    /// When set, sizes are printed as a number of such blocks, rounded up like
+    /// `du --block-size`; a block size of 1 prints raw bytes.
+    pub block_size: Option<Size>,
+}
+
+/// Implements Default as IEC units with one decimal.
+impl Default for SizeFormat {
+    fn default() -> Self {
+        SizeFormat { base: UnitBase::Iec, precision: 1, block_size: None }
+    }
+}
+
+/// Implements the formatting of sizes.
+impl SizeFormat {
+    /// Raw byte counts, with no unit.
+    pub fn bytes() -> Self {
+        SizeFormat { block_size: Some(Size(1)), ..Default::default() }
+    }
+
+    /// Formats `size`, e.g. `1.5 MiB`, `1.6 MB` or `1572864`.
+    pub fn format(&self, size: Size) -> String {
+        if let Some(block_size) = self.block_size {
+            return size.0.div_ceil(block_size.0.max(1)).to_string();
         }
-        else if mb <= size && size < gb {
-            unit = "MB";
-            size = size/mb;
+        let (base, units) = match self.base {
+            UnitBase::Iec => (1024.0, IEC_UNITS),
+            UnitBase::Si => (1000.0, SI_UNITS),
+        };
+        if (size.0 as f64) < base {
+            return format!("{} B", size.0);
         }
-        else if gb <= size {
-            unit = "GB";
-            size = size/gb;
+        let mut value = size.0 as f64;
+        let mut unit = 0;
+        // passe à l'unité suivante si l'arrondi atteint la base (1023.96 KiB -> 1.0 MiB)
+        let factor = 10f64.powi(self.precision as i32);
+        while unit + 1 < units.len() && (value * factor).round() / factor >= base {
+            value /= base;
+            unit += 1;
         }
+        format!("{:.*} {}", self.precision, value, units[unit])
+    }
+}
 
-        write!(f, "{} {:.1}", unit, size)
+/// Implements Display with the default format, e.g. `2.0 KiB`.
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", SizeFormat::default().format(*self))
     }
 }
 
-/// Implements the addition operator for the Size structure.
-impl std::ops::Add for Size {
+/// Implements the addition operator for the Size structure; it saturates
+/// instead of overflowing.
+impl Add for Size {
     type Output = Self;
     fn add(self, other: Self) -> Self::Output {
-        Size(self.0 + other.0)
+        self.saturating_add(other)
     }
 }
 
-/// Implements the conversion from u64 to Size with error handling.
-impl TryFrom<u64> for Size {
-    type Error = SizeError;
+/// Implements `+=` for the Size structure.
+impl AddAssign for Size {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
 
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        if value <= u64::MAX {
-            Ok(Size(value))
-        } else {
-            Err(SizeError)
+/// Implements the subtraction operator, e.g. to diff two snapshots; it stops
+/// at zero, use `checked_sub` to detect a negative difference.
+impl Sub for Size {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self::Output {
+        self.saturating_sub(other)
+    }
+}
+
+/// Implements `-=` for the Size structure.
+impl SubAssign for Size {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+/// Implements scaling a size by a count; it saturates instead of overflowing.
+impl Mul<u64> for Size {
+    type Output = Self;
+    fn mul(self, factor: u64) -> Self::Output {
+        self.saturating_mul(factor)
+    }
+}
+
+/// Implements dividing a size into `divisor` equal parts, rounded down.
+impl Div<u64> for Size {
+    type Output = Self;
+    fn div(self, divisor: u64) -> Self::Output {
+        Size(self.0 / divisor)
+    }
+}
+
+/// Implements summing an iterator of sizes.
+impl Sum for Size {
+    fn sum<I: Iterator<Item = Size>>(iter: I) -> Self {
+        iter.fold(Size(0), Add::add)
+    }
+}
+
+/// Implements summing an iterator of borrowed sizes.
+impl<'a> Sum<&'a Size> for Size {
+    fn sum<I: Iterator<Item = &'a Size>>(iter: I) -> Self {
+        iter.copied().sum()
+    }
+}
+
+/// Implements the conversion from u64 to Size.
+impl From<u64> for Size {
+    fn from(value: u64) -> Self {
+        Size::new(value)
+    }
+}
+
+/// Parses sizes such as `42`, `3MB`, `200KiB` or `1.5G`.
+///
+/// Units are case-insensitive. A bare prefix (`K`, `M`, ...) and the IEC forms
+/// (`KiB`, `MiB`, ...) are powers of 1024, as in `du -B`; the SI forms (`kB`,
+/// `MB`, ...) are powers of 1000. Fractional values are rounded down to a byte.
+impl FromStr for Size {
+    type Err = SizeError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(SizeError::Empty);
+        }
+        let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+        let (number, unit) = text.split_at(split);
+        // un signe ou une lettre en tête n'est pas une unité inconnue
+        if number.is_empty() {
+            return Err(SizeError::InvalidNumber(text.to_string()));
+        }
+        let unit = unit.trim_start();
+
+        let exponent = match unit.chars().next().map(|c| c.to_ascii_uppercase()) {
+            None | Some('B') if unit.len() <= 1 => 0,
+            Some('K') => 1,
+            Some('M') => 2,
+            Some('G') => 3,
+            Some('T') => 4,
+            Some('P') => 5,
+            Some('E') => 6,
+            _ => return Err(SizeError::UnknownUnit(unit.to_string())),
+        };
+        let base: u64 = match unit.get(1..).map(str::to_ascii_lowercase).as_deref() {
+            Some("") | Some("ib") | None => 1024,
+            Some("b") if exponent > 0 => 1000,
+            _ => return Err(SizeError::UnknownUnit(unit.to_string())),
+        };
+        let overflow = || SizeError::Overflow(text.to_string());
+        let multiplier = base.checked_pow(exponent).ok_or_else(overflow)?;
+
+        // les entiers sont calculés exactement, sans passer par un flottant
+        if let Ok(bytes) = number.parse::<u64>() {
+            return bytes.checked_mul(multiplier).map(Size).ok_or_else(overflow);
         }
+        let value: f64 = number
+            .parse()
+            .ok()
+            .filter(|value: &f64| value.is_finite())
+            .ok_or_else(|| SizeError::InvalidNumber(text.to_string()))?;
+        let bytes = value * multiplier as f64;
+        if bytes >= u64::MAX as f64 {
+            return Err(overflow());
+        }
+        Ok(Size(bytes as u64))
     }
 }
 
 /// Module containing tests for the Size structure.
+#[cfg(test)]
 mod tests {
     use super::*;
 
     /// Tests the creation of a new Size instance.
     #[test]
     fn test_new() {
-        let new = Size::new(78564);
-        assert!(new.is_ok());
-        assert_eq!(new.unwrap().0, 78564);
+        assert_eq!(Size::new(78564).0, 78564);
+        assert_eq!(Size::from(78564), Size(78564));
     }
 
     /// Tests the addition operation for the Size structure.
@@ -104,27 +309,142 @@ mod tests {
         assert_eq!(c.0, 14443);
     }
 
+    /// Tests that addition saturates and checked operations report overflow.
+    #[test]
+    fn test_overflow() {
+        assert_eq!(Size(u64::MAX) + Size(1), Size(u64::MAX));
+        assert_eq!(Size(u64::MAX).checked_add(Size(1)), None);
+        assert_eq!(Size(u64::MAX / 2) * 3, Size(u64::MAX));
+        assert_eq!(Size(u64::MAX / 2).checked_mul(3), None);
+    }
+
+    /// Tests subtraction, which stops at zero.
+    #[test]
+    fn test_subtraction() {
+        assert_eq!(Size(10) - Size(3), Size(7));
+        assert_eq!(Size(3) - Size(10), Size(0));
+        assert_eq!(Size(3).checked_sub(Size(10)), None);
+        let mut size = Size(10);
+        size -= Size(4);
+        size += Size(1);
+        assert_eq!(size, Size(7));
+    }
+
+    /// Tests summing sizes and division.
+    #[test]
+    fn test_sum_and_division() {
+        let sizes = [Size(1), Size(2), Size(3)];
+        assert_eq!(sizes.iter().sum::<Size>(), Size(6));
+        assert_eq!(sizes.into_iter().sum::<Size>(), Size(6));
+        assert_eq!(Size(7) / 2, Size(3));
+    }
+
+    /// Tests ratios and percentages, including an empty total.
+    #[test]
+    fn test_ratio_and_percent() {
+        assert_eq!(Size(1).ratio(Size(4)), 0.25);
+        assert_eq!(Size(1).percent_of(Size(4)), 25.0);
+        assert_eq!(Size(5).percent_of(Size(0)), 0.0);
+    }
+
     /// Tests the display for sizes in bytes.
     #[test]
     fn test_display_bytes() {
-        assert_eq!(Size(1023).to_string(), "B 1023.0");
+        assert_eq!(Size(0).to_string(), "0 B");
+        assert_eq!(Size(1023).to_string(), "1023 B");
     }
 
-    /// Tests the display for sizes in kilobytes.
+    /// Tests the display for sizes in kibibytes, including exactly 1024 bytes.
     #[test]
     fn test_display_kilobytes() {
-        assert_eq!(Size(2048).to_string(), "KB 2.0");
+        assert_eq!(Size(1024).to_string(), "1.0 KiB");
+        assert_eq!(Size(2048).to_string(), "2.0 KiB");
     }
 
-    /// Tests the display for sizes in megabytes.
+    /// Tests the display for sizes in mebibytes.
     #[test]
     fn test_display_megabytes() {
-        assert_eq!(Size(2 * 1024 * 1024).to_string(), "MB 2.0");
+        assert_eq!(Size(2 * 1024 * 1024).to_string(), "2.0 MiB");
     }
 
-    /// Tests the display for sizes in gigabytes.
+    /// Tests the display for sizes in gibibytes.
     #[test]
     fn test_display_gigabytes() {
-        assert_eq!(Size(2 * 1024 * 1024 * 1024).to_string(), "GB 2.0");
+        assert_eq!(Size(2 * 1024 * 1024 * 1024).to_string(), "2.0 GiB");
+    }
+
+    /// Tests the display of units up to exbibytes.
+    #[test]
+    fn test_display_large_units() {
+        assert_eq!(Size(3 << 40).to_string(), "3.0 TiB");
+        assert_eq!(Size(5 << 50).to_string(), "5.0 PiB");
+        assert_eq!(Size(u64::MAX).to_string(), "16.0 EiB");
+    }
+
+    /// Tests that a value rounding up to the base moves to the next unit.
+    #[test]
+    fn test_display_rounds_to_next_unit() {
+        assert_eq!(Size(1024 * 1024 - 1).to_string(), "1.0 MiB");
+    }
+
+    /// Tests SI units and the precision setting.
+    #[test]
+    fn test_format_si_and_precision() {
+        let si = SizeFormat { base: UnitBase::Si, ..Default::default() };
+        assert_eq!(si.format(Size(999)), "999 B");
+        assert_eq!(si.format(Size(1500)), "1.5 kB");
+        assert_eq!(si.format(Size(2_000_000_000_000)), "2.0 TB");
+        let precise = SizeFormat { precision: 3, ..Default::default() };
+        assert_eq!(precise.format(Size(1536 * 1024)), "1.500 MiB");
+    }
+
+    /// Tests raw bytes and fixed block sizes.
+    #[test]
+    fn test_format_bytes_and_blocks() {
+        assert_eq!(SizeFormat::bytes().format(Size(123456789)), "123456789");
+        let kib_blocks = SizeFormat { block_size: Some(Size(1024)), ..Default::default() };
+        assert_eq!(kib_blocks.format(Size(0)), "0");
+        assert_eq!(kib_blocks.format(Size(1)), "1");
+        assert_eq!(kib_blocks.format(Size(4096)), "4");
+        assert_eq!(kib_blocks.format(Size(4097)), "5");
+    }
+
+    /// Tests parsing plain byte counts.
+    #[test]
+    fn test_parse_bytes() {
+        assert_eq!("42".parse(), Ok(Size(42)));
+        assert_eq!("42B".parse(), Ok(Size(42)));
+        assert_eq!(" 7 b ".parse(), Ok(Size(7)));
+    }
+
+    /// Tests parsing IEC and bare suffixes, which are powers of 1024.
+    #[test]
+    fn test_parse_iec() {
+        assert_eq!("200KiB".parse(), Ok(Size(200 * 1024)));
+        assert_eq!("1.5G".parse(), Ok(Size(3 << 29)));
+        assert_eq!("2m".parse(), Ok(Size(2 << 20)));
+        assert_eq!("1EiB".parse(), Ok(Size(1 << 60)));
+    }
+
+    /// Tests parsing SI suffixes, which are powers of 1000.
+    #[test]
+    fn test_parse_si() {
+        assert_eq!("3MB".parse(), Ok(Size(3_000_000)));
+        assert_eq!("1.5kB".parse(), Ok(Size(1500)));
+        assert_eq!("2 TB".parse(), Ok(Size(2_000_000_000_000)));
+    }
+
+    /// Tests that invalid sizes explain what was wrong.
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!("".parse::<Size>(), Err(SizeError::Empty));
+        assert_eq!("MB".parse::<Size>(), Err(SizeError::InvalidNumber("MB".to_string())));
+        assert_eq!("1.2.3K".parse::<Size>(), Err(SizeError::InvalidNumber("1.2.3K".to_string())));
+        assert_eq!("-5".parse::<Size>(), Err(SizeError::InvalidNumber("-5".to_string())));
+        assert_eq!("+5M".parse::<Size>(), Err(SizeError::InvalidNumber("+5M".to_string())));
+        assert_eq!("10Q".parse::<Size>(), Err(SizeError::UnknownUnit("Q".to_string())));
+        assert_eq!("10KBB".parse::<Size>(), Err(SizeError::UnknownUnit("KBB".to_string())));
+        assert_eq!("16E".parse::<Size>(), Err(SizeError::Overflow("16E".to_string())));
+        assert_eq!("20000PB".parse::<Size>(), Err(SizeError::Overflow("20000PB".to_string())));
     }
 }
@@ -0,0 +1,216 @@
+// Sorties en flux : un enregistrement par entrée, écrit pendant le parcours,
+// pour les arbres trop grands pour être gardés en mémoire.
+//
+// Chaque enregistrement porte les champs :
+//   "depth"              profondeur de l'entrée, 0 pour la racine
+//   "kind"               "file", "directory", "symlink", "mount_point" ou "error"
+//   "path"               chemin complet de l'entrée
+//   "parent"             chemin du répertoire parent, vide (null en NDJSON) pour la racine
+//   "apparent_size"      taille apparente en octets (cumulée pour un répertoire)
+//   "disk_size"          espace alloué en octets (cumulé pour un répertoire)
+//   "mtime"              date de modification en secondes depuis l'époque Unix,
+//                        vide (null) si elle est inconnue
+//   "counted_elsewhere"  fichier dont la taille est comptée par un autre lien physique
+//
+// Un répertoire est écrit après tout son contenu, une fois ses tailles
+// cumulées connues : la racine est le dernier enregistrement. Les enfants
+// d'un répertoire apparaissent triés par chemin.
+//
+// En NDJSON, chaque ligne est un objet JSON. En CSV, la première ligne donne
+// les noms des colonnes, dans l'ordre ci-dessus ; un champ contenant une
+// virgule, un guillemet ou un saut de ligne est placé entre guillemets.
+
+use crate::file_tree::EntryNode;
+use crate::json::to_unix_seconds;
+use serde::Serialize;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Formats d'enregistrement disponibles
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamFormat {
+    Ndjson,
+    Csv,
+}
+
+const CSV_HEADER: &str = "depth,kind,path,parent,apparent_size,disk_size,mtime,counted_elsewhere";
+
+#[derive(Serialize)]
+struct Record<'a> {
+    depth: usize,
+    kind: &'static str,
+    path: &'a str,
+    parent: Option<&'a str>,
+    apparent_size: u64,
+    disk_size: u64,
+    mtime: Option<i64>,
+    counted_elsewhere: bool,
+}
+
+fn kind(node: &EntryNode) -> &'static str {
+    match node {
+        EntryNode::File { .. } => "file",
+        EntryNode::Directory { .. } => "directory",
+        EntryNode::Symlink { .. } => "symlink",
+        EntryNode::MountPoint { .. } => "mount_point",
+        EntryNode::Error { .. } => "error",
+    }
+}
+
+// Place un champ CSV entre guillemets si nécessaire
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Écrit les enregistrements un par un, sans rien garder en mémoire
+pub struct RecordWriter<W: Write> {
+    format: StreamFormat,
+    writer: BufWriter<W>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    // Écrit l'en-tête éventuel du format
+    pub fn new(format: StreamFormat, writer: W) -> io::Result<Self> {
+        let mut writer = BufWriter::new(writer);
+        if format == StreamFormat::Csv {
+            writeln!(writer, "{CSV_HEADER}")?;
+        }
+        Ok(RecordWriter { format, writer })
+    }
+
+    // Écrit l'enregistrement du nœud `path`, situé à la profondeur `depth`
+    pub fn write(&mut self, path: &Path, depth: usize, node: &EntryNode) -> io::Result<()> {
+        let path_string = path.to_string_lossy();
+        let parent_string = path.parent().filter(|_| depth > 0).map(|parent| parent.to_string_lossy());
+        let record = Record {
+            depth,
+            kind: kind(node),
+            path: &path_string,
+            parent: parent_string.as_deref(),
+            apparent_size: node.apparent_size().0,
+            disk_size: node.disk_size().0,
+            mtime: node.modified().map(to_unix_seconds),
+            counted_elsewhere: matches!(node, EntryNode::File { counted_elsewhere: true, .. }),
+        };
+        match self.format {
+            StreamFormat::Ndjson => serde_json::to_writer(&mut self.writer, &record)?,
+            StreamFormat::Csv => write!(
+                self.writer,
+                "{},{},{},{},{},{},{},{}",
+                record.depth,
+                record.kind,
+                csv_field(record.path),
+                csv_field(record.parent.unwrap_or_default()),
+                record.apparent_size,
+                record.disk_size,
+                record.mtime.map(|mtime| mtime.to_string()).unwrap_or_default(),
+                record.counted_elsewhere,
+            )?,
+        }
+        writeln!(self.writer)
+    }
+
+    // Vide le tampon ; à appeler une fois le dernier enregistrement écrit
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_tree::tests::sample_tree;
+    use crate::file_tree::{FileTree, ScanOptions};
+    use crate::size::Size;
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    // Streams `root` into a list of (path, depth, apparent size, kind)
+    fn stream(root: &Path) -> Vec<(PathBuf, usize, Size, &'static str)> {
+        let mut records = vec![];
+        let errors = FileTree::stream(root, &ScanOptions::default(), &mut |path, depth, node| {
+            records.push((path.to_path_buf(), depth, node.apparent_size(), kind(node)));
+            Ok(())
+        })
+        .unwrap();
+        assert!(errors.is_empty());
+        records
+    }
+
+    /// Tests that directories are written after their content, with cumulative sizes.
+    #[test]
+    fn test_stream_is_post_order_with_cumulative_sizes() {
+        let (temp_dir, file_tree) = sample_tree();
+        let root = temp_dir.path();
+
+        let records = stream(root);
+        let expected = vec![
+            (root.join("big/deep/leaf.txt"), 3, Size(300), "file"),
+            (root.join("big/deep"), 2, Size(300), "directory"),
+            (root.join("big/mid.txt"), 2, Size(200), "file"),
+            (root.join("big"), 1, Size(500), "directory"),
+            (root.join("small.txt"), 1, Size(100), "file"),
+            (root.to_path_buf(), 0, Size(600), "directory"),
+        ];
+        assert_eq!(records, expected);
+
+        // The loaded tree is visited in the same order with the same sizes
+        let mut visited = vec![];
+        file_tree
+            .visit(&mut |path, depth, node| {
+                visited.push((path.to_path_buf(), depth, node.apparent_size(), kind(node)));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(visited, expected);
+    }
+
+    /// Tests the fields and quoting of NDJSON and CSV records.
+    #[test]
+    fn test_ndjson_and_csv_records() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("a,\"b\".txt"), vec![0; 10]).unwrap();
+
+        let write = |format| {
+            let mut out = vec![];
+            let mut writer = RecordWriter::new(format, &mut out).unwrap();
+            FileTree::stream(root, &ScanOptions::default(), &mut |path, depth, node| {
+                writer.write(path, depth, node).unwrap();
+                Ok(())
+            })
+            .unwrap();
+            writer.finish().unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let ndjson = write(StreamFormat::Ndjson);
+        let lines: Vec<serde_json::Value> =
+            ndjson.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["kind"], "file");
+        assert_eq!(lines[0]["depth"], 1);
+        assert_eq!(lines[0]["parent"], root.to_string_lossy().as_ref());
+        assert_eq!(lines[0]["apparent_size"], 10);
+        assert_eq!(lines[1]["kind"], "directory");
+        assert_eq!(lines[1]["parent"], serde_json::Value::Null);
+        assert_eq!(lines[1]["apparent_size"], 10);
+
+        let csv = write(StreamFormat::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        let file = root.join("a,\"b\".txt");
+        assert!(lines[1].starts_with(&format!(
+            "1,file,\"{}\",{},10,",
+            file.to_string_lossy().replace('"', "\"\""),
+            root.to_string_lossy()
+        )));
+        assert!(lines[1].ends_with(",false"));
+        assert!(lines[2].starts_with(&format!("0,directory,{},,10,", root.to_string_lossy())));
+    }
+}
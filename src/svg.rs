@@ -0,0 +1,339 @@
+// Image SVG statique de l'arbre : une carte proportionnelle (treemap) où
+// chaque répertoire est un cadre titré qui contient ses entrées, et chaque
+// fichier un rectangle coloré selon la catégorie de son extension (voir
+// report.rs). Les entrées assez grandes portent leur nom et leur taille ;
+// toutes ont une infobulle <title> avec le chemin complet. Une légende des
+// catégories occupe le bas de l'image.
+
+use crate::file_tree::{EntryNode, FileTree};
+use crate::report::{category_of, CATEGORIES};
+use crate::size::{Size, SizeFormat};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Hauteur du titre d'un répertoire, et des bandeaux de titre et de légende
+const HEADER: f64 = 14.0;
+const BAND: f64 = 24.0;
+// En dessous de ce côté, en pixels, une entrée n'est pas dessinée
+const MIN_SIDE: f64 = 3.0;
+// Largeur moyenne d'un caractère à la taille de police utilisée
+const CHAR_WIDTH: f64 = 6.5;
+const FONT_SIZE: f64 = 11.0;
+const DIRECTORY_COLOR: &str = "#e4e4e4";
+
+// Options du rendu
+#[derive(Clone, Debug)]
+pub struct SvgOptions {
+    pub width: u32,
+    pub height: u32,
+    // les répertoires à cette profondeur sont dessinés sans leur contenu
+    pub max_depth: Option<usize>,
+    pub size_format: SizeFormat,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions { width: 1200, height: 800, max_depth: None, size_format: SizeFormat::default() }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+// Disposition « squarified » (Bruls, Huizing, van Wijk) : les tailles, non
+// nulles et triées par ordre décroissant, sont rangées par rangées le long du
+// plus petit côté du rectangle restant, en gardant des proportions proches du
+// carré. Renvoie un rectangle par taille, dans le même ordre.
+pub fn squarify(sizes: &[u64], area: Rect) -> Vec<Rect> {
+    let mut placed = Vec::with_capacity(sizes.len());
+    let Rect { mut x, mut y, mut width, mut height } = area;
+    let mut remaining: f64 = sizes.iter().map(|&size| size as f64).sum();
+    let mut next = 0;
+    while next < sizes.len() && remaining > 0.0 && width > 0.0 && height > 0.0 {
+        let side = width.min(height);
+        let scale = width * height / remaining;
+        let start = next;
+        let (mut row_area, mut worst) = (0.0, f64::INFINITY);
+        // on allonge la rangée tant que son pire rapport largeur/hauteur s'améliore
+        while next < sizes.len() {
+            let area = sizes[next] as f64 * scale;
+            let total = row_area + area;
+            let largest = sizes[start] as f64 * scale;
+            let ratio = f64::max(side * side * largest / (total * total), total * total / (side * side * area));
+            if next > start && ratio > worst {
+                break;
+            }
+            row_area = total;
+            worst = ratio;
+            next += 1;
+        }
+
+        let thickness = row_area / side;
+        let mut offset = 0.0;
+        for &size in &sizes[start..next] {
+            let length = size as f64 * scale / thickness;
+            placed.push(if width >= height {
+                Rect { x, y: y + offset, width: thickness, height: length }
+            } else {
+                Rect { x: x + offset, y, width: length, height: thickness }
+            });
+            offset += length;
+        }
+        if width >= height {
+            x += thickness;
+            width -= thickness;
+        } else {
+            y += thickness;
+            height -= thickness;
+        }
+        remaining -= row_area / scale;
+    }
+    placed
+}
+
+// Échappe un texte pour le placer dans un document XML. Les caractères de
+// contrôle interdits par XML 1.0, qu'un nom de fichier peut contenir, sont
+// remplacés par U+FFFD
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => escaped.push(char::REPLACEMENT_CHARACTER),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Texte affichable sur `width` pixels, raccourci au besoin ; None s'il ne
+// resterait presque rien
+fn fit_label(text: &str, width: f64) -> Option<String> {
+    let max_chars = ((width - 6.0) / CHAR_WIDTH).max(0.0) as usize;
+    let chars = text.chars().count();
+    if chars <= max_chars {
+        Some(text.to_string())
+    } else if max_chars >= 4 {
+        Some(text.chars().take(max_chars - 1).chain(['…']).collect())
+    } else {
+        None
+    }
+}
+
+impl FileTree {
+    // Dessine récursivement `path` dans `rect`
+    fn write_svg_node<W: Write>(
+        &self,
+        writer: &mut W,
+        path: &Path,
+        rect: Rect,
+        depth: usize,
+        options: &SvgOptions,
+    ) -> io::Result<()> {
+        if rect.width < MIN_SIDE || rect.height < MIN_SIDE {
+            return Ok(());
+        }
+        let size = self.get_size(path).unwrap_or_default();
+        let size_label = options.size_format.format(size);
+        let name = match path.file_name() {
+            Some(name) if depth > 0 => name.to_string_lossy(),
+            _ => path.to_string_lossy(),
+        };
+        let children = match self.map.get(path) {
+            Some(EntryNode::Directory { children, .. }) => Some(children),
+            _ => None,
+        };
+        let color = match children {
+            Some(_) => DIRECTORY_COLOR,
+            None => CATEGORIES[category_of(path)].color,
+        };
+        writeln!(
+            writer,
+            r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}"><title>{} ({})</title></rect>"#,
+            rect.x,
+            rect.y,
+            rect.width,
+            rect.height,
+            color,
+            escape(&path.to_string_lossy()),
+            size_label
+        )?;
+
+        // nom, puis taille sur une seconde ligne s'il y a la place
+        if rect.height >= FONT_SIZE + 2.0 {
+            let label = match children {
+                Some(_) => fit_label(&format!("{} ({})", name, size_label), rect.width),
+                None => fit_label(&name, rect.width),
+            };
+            if let Some(label) = label {
+                writeln!(writer, r#"<text x="{:.1}" y="{:.1}">{}</text>"#, rect.x + 3.0, rect.y + FONT_SIZE, escape(&label))?;
+            }
+            let second_line = children.is_none() && rect.height >= 2.0 * FONT_SIZE + 4.0;
+            if let Some(label) = fit_label(&size_label, rect.width).filter(|_| second_line) {
+                writeln!(
+                    writer,
+                    r#"<text x="{:.1}" y="{:.1}">{}</text>"#,
+                    rect.x + 3.0,
+                    rect.y + 2.0 * FONT_SIZE + 1.0,
+                    escape(&label)
+                )?;
+            }
+        }
+
+        let Some(children) = children else {
+            return Ok(());
+        };
+        if options.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            return Ok(());
+        }
+        let inner = Rect {
+            x: rect.x + 2.0,
+            y: rect.y + HEADER,
+            width: rect.width - 4.0,
+            height: rect.height - HEADER - 2.0,
+        };
+        let mut sized: Vec<(&Path, Size)> = children
+            .iter()
+            .map(|child| (child.as_path(), self.counted_size(child).unwrap_or_default()))
+            .filter(|(_, size)| *size > Size(0))
+            .collect();
+        sized.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+        let sizes: Vec<u64> = sized.iter().map(|(_, size)| size.0).collect();
+        for ((child, _), child_rect) in sized.iter().zip(squarify(&sizes, inner)) {
+            self.write_svg_node(writer, child, child_rect, depth + 1, options)?;
+        }
+        Ok(())
+    }
+
+    // Écrit l'image SVG de l'arbre : un titre, la carte, puis la légende
+    pub fn write_svg<W: Write>(&self, writer: W, options: &SvgOptions) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        let (width, height) = (options.width as f64, options.height as f64);
+        let root = self.get_root();
+        writeln!(
+            writer,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}" font-family="sans-serif" font-size="{}">"#,
+            options.width, options.height, options.width, options.height, FONT_SIZE
+        )?;
+        writeln!(writer, r##"<rect width="100%" height="100%" fill="#fff"/>"##)?;
+        let total = options.size_format.format(self.get_size(root).unwrap_or_default());
+        writeln!(
+            writer,
+            r#"<text x="4" y="16" font-size="14" font-weight="bold">{} ({})</text>"#,
+            escape(&root.to_string_lossy()),
+            total
+        )?;
+
+        writeln!(writer, r##"<g stroke="#fff" stroke-width="1">"##)?;
+        let map = Rect { x: 0.0, y: BAND, width, height: height - 2.0 * BAND };
+        self.write_svg_node(&mut writer, root, map, 0, options)?;
+        writeln!(writer, "</g>")?;
+
+        // légende, sur une ligne en bas de l'image
+        let mut x = 4.0;
+        let y = height - BAND / 2.0;
+        for category in &CATEGORIES {
+            writeln!(
+                writer,
+                r#"<rect x="{:.1}" y="{:.1}" width="10" height="10" fill="{}"/><text x="{:.1}" y="{:.1}">{}</text>"#,
+                x,
+                y - 8.0,
+                category.color,
+                x + 14.0,
+                y + 1.0,
+                category.name
+            )?;
+            x += 24.0 + category.name.len() as f64 * CHAR_WIDTH;
+        }
+        writeln!(writer, "</svg>")?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_squarify() {
+        // The example from the original paper: 6x4 split for 6, 6, 4, 3, 2, 2, 1
+        let area = Rect { x: 0.0, y: 0.0, width: 6.0, height: 4.0 };
+        let rects = squarify(&[6, 6, 4, 3, 2, 2, 1], area);
+        assert_eq!(rects.len(), 7);
+        assert_eq!(rects[0], Rect { x: 0.0, y: 0.0, width: 3.0, height: 2.0 });
+        assert_eq!(rects[1], Rect { x: 0.0, y: 2.0, width: 3.0, height: 2.0 });
+        // Every rectangle keeps its share of the area and stays inside
+        for (rect, size) in rects.iter().zip([6, 6, 4, 3, 2, 2, 1]) {
+            assert!((rect.width * rect.height - size as f64).abs() < 1e-9);
+            assert!(rect.x >= 0.0 && rect.x + rect.width <= 6.0 + 1e-9);
+            assert!(rect.y >= 0.0 && rect.y + rect.height <= 4.0 + 1e-9);
+        }
+        assert!(squarify(&[], area).is_empty());
+    }
+
+    #[test]
+    fn test_fit_label() {
+        assert_eq!(fit_label("abc", 100.0), Some("abc".to_string()));
+        assert_eq!(fit_label("a_rather_long_name", 50.0), Some("a_rat…".to_string()));
+        assert_eq!(fit_label("a_rather_long_name", 20.0), None);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("<a & 'b'>\"c\""), "&lt;a &amp; &apos;b&apos;&gt;&quot;c&quot;");
+        // Control characters XML 1.0 forbids are replaced, tab and newlines are kept
+        assert_eq!(escape("a\x01b\x1fc"), "a\u{fffd}b\u{fffd}c");
+        assert_eq!(escape("a\tb\nc\r"), "a\tb\nc\r");
+    }
+
+    #[test]
+    fn test_write_svg() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("photos/old")).unwrap();
+        fs::write(root.join("photos/old/a&b.jpg"), vec![0; 600_000]).unwrap();
+        fs::write(root.join("notes.txt"), vec![0; 400_000]).unwrap();
+        // A second name for a&b.jpg adds nothing and gets no tile
+        #[cfg(unix)]
+        fs::hard_link(root.join("photos/old/a&b.jpg"), root.join("photos/old/copy.jpg")).unwrap();
+        let file_tree = FileTree::new(root).unwrap();
+
+        let render = |max_depth| {
+            let mut out = vec![];
+            let options = SvgOptions { max_depth, ..Default::default() };
+            file_tree.write_svg(&mut out, &options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let svg = render(None);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"1200\" height=\"800\""));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        // Large nodes are labelled, names are escaped and files take their category colour
+        assert!(svg.contains(">photos (585.9 KiB)</text>"));
+        assert!(svg.contains(">notes.txt</text>"));
+        assert!(svg.contains(">a&amp;b.jpg</text>"));
+        assert!(svg.contains(&format!("fill=\"{}\"><title>{}", CATEGORIES[0].color, escape(&root.join("photos/old/a&b.jpg").to_string_lossy()))));
+        assert!(!svg.contains("copy.jpg"));
+        // The legend lists every category
+        for category in &CATEGORIES {
+            assert!(svg.contains(&format!(">{}</text>", category.name)));
+        }
+
+        // With a depth limit, the content of photos/ is not drawn
+        let svg = render(Some(1));
+        assert!(svg.contains(">photos (585.9 KiB)</text>"));
+        assert!(!svg.contains("a&amp;b.jpg"));
+    }
+}